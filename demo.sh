@@ -39,6 +39,27 @@ secretkey2=$(echo "$keypair2" | head -1 | cut -d " " -f3)
 printf "secret share: %s\npublic share: %s \n\n" "$(short_print "$secretkey2")" "$(short_print "$pubkey2")"
 sleep 0.3s
 
+party_1 "Prove holding the share"
+printf "$ solana-tss setup prove --keypair %s --keys %s --keys %s\n" "$(short_print "$secretkey1")" "$(short_print "$pubkey1")" "$(short_print "$pubkey2")"
+sleep 0.6s
+proof1=$(solana-tss setup prove --keypair "$secretkey1" --keys "$pubkey1" --keys "$pubkey2" | cut -d " " -f2)
+printf "Proof: %s\n" "$(short_print "$proof1")"
+sleep 0.3s
+
+party_2 "Prove holding the share"
+printf "$ solana-tss setup prove --keypair %s --keys %s --keys %s\n" "$(short_print "$secretkey2")" "$(short_print "$pubkey1")" "$(short_print "$pubkey2")"
+sleep 0.6s
+proof2=$(solana-tss setup prove --keypair "$secretkey2" --keys "$pubkey1" --keys "$pubkey2" | cut -d " " -f2)
+printf "Proof: %s\n\n" "$(short_print "$proof2")"
+sleep 0.3s
+
+all_parties "Verify every proof and store the wallet"
+printf "$ solana-tss setup verify --keys %s --keys %s --proofs %s --proofs %s\n" "$(short_print "$pubkey1")" "$(short_print "$pubkey2")" "$(short_print "$proof1")" "$(short_print "$proof2")"
+sleep 0.6s
+solana-tss setup verify --keys "$pubkey1" --keys "$pubkey2" --proofs "$proof1" --proofs "$proof2"
+echo
+sleep 0.3s
+
 all_parties "Aggregate the Shares(either party can execute)"
 printf "$ solana-tss aggregate-keys %s %s\n" "$(short_print "$pubkey1")" "$(short_print "$pubkey2")"
 sleep 0.6s
@@ -94,20 +115,20 @@ printf "Recent block hash: %s\n\n" "$(short_print "$recent_block_hash")"
 sleep 0.3s
 
 party_1 "Process message 1 and generate message 2"
-printf "$ solana-tss agg-send-step-two --keypair %s --to %s --amount 0.1 --memo \"ZenGo: 2 Party Signing\" --keys %s --keys %s --recent-block-hash %s --first-messages %s --secret-state %s\n" \
+printf "$ solana-tss agg-send-step-two --yes --keypair %s --to %s --amount 0.1 --memo \"ZenGo: 2 Party Signing\" --keys %s --keys %s --recent-block-hash %s --first-messages %s --secret-state %s\n" \
   "$(short_print "$secretkey1")" "$(short_print "$reciever_key")" "$(short_print "$pubkey1")" "$(short_print "$pubkey2")" "$(short_print "$recent_block_hash")" "$(short_print "$party2msg1")" "$(short_print "$party1state")"
 sleep 0.6s
-party1_raw=$( solana-tss agg-send-step-two --keypair "$secretkey1" --to "$reciever_key" --amount 0.1 --memo "ZenGo: 2 Party Signing" --keys "$pubkey1" --keys "$pubkey2" --recent-block-hash "$recent_block_hash" --first-messages "$party2msg1" --secret-state "$party1state" )
+party1_raw=$( solana-tss agg-send-step-two --yes --keypair "$secretkey1" --to "$reciever_key" --amount 0.1 --memo "ZenGo: 2 Party Signing" --keys "$pubkey1" --keys "$pubkey2" --recent-block-hash "$recent_block_hash" --first-messages "$party2msg1" --secret-state "$party1state" )
 partialsig1=$(echo "$party1_raw" | cut -d " " -f3)
 printf "Partial signature: %s\n" "$(short_print "$partialsig1")"
 sleep 0.3s
 
 
 party_2 "Process message 1 and generate message 2"
-printf "$ solana-tss agg-send-step-two --keypair %s --to %s --amount 0.1 --memo \"ZenGo: 2 Party Signing\" --keys %s --keys %s --recent-block-hash %s --first-messages %s --secret-state %s\n" \
+printf "$ solana-tss agg-send-step-two --yes --keypair %s --to %s --amount 0.1 --memo \"ZenGo: 2 Party Signing\" --keys %s --keys %s --recent-block-hash %s --first-messages %s --secret-state %s\n" \
   "$(short_print "$secretkey2")" "$(short_print "$reciever_key")" "$(short_print "$pubkey1")" "$(short_print "$pubkey2")" "$(short_print "$recent_block_hash")" "$(short_print "$party1msg1")" "$(short_print "$party2state")"
 sleep 0.6s
-party2_raw=$( solana-tss agg-send-step-two --keypair "$secretkey2" --to "$reciever_key" --amount 0.1 --memo "ZenGo: 2 Party Signing" --keys "$pubkey1" --keys "$pubkey2" --recent-block-hash "$recent_block_hash" --first-messages "$party1msg1" --secret-state "$party2state" )
+party2_raw=$( solana-tss agg-send-step-two --yes --keypair "$secretkey2" --to "$reciever_key" --amount 0.1 --memo "ZenGo: 2 Party Signing" --keys "$pubkey1" --keys "$pubkey2" --recent-block-hash "$recent_block_hash" --first-messages "$party1msg1" --secret-state "$party2state" )
 partialsig2=$(echo "$party2_raw" | cut -d " " -f3)
 printf "Partial signature: %s\n\n" "$(short_print "$partialsig2")"
 sleep 0.3s
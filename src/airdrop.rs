@@ -0,0 +1,157 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+
+use crate::chain::Chain;
+use crate::Error;
+
+const MAX_ATTEMPTS: u32 = 5;
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+pub struct AirdropReport {
+    pub signature: Signature,
+    pub requested: u64,
+    pub credited: u64,
+    pub balance: u64,
+    pub attempts: u32,
+}
+
+/// Request an airdrop and make sure the funds actually arrived.
+/// Faucets can accept a request and then never land it, so we watch the signature status, and ask again if the airdrop
+/// failed or the cluster didn't hear of it within its share of `timeout`. Once the cluster knows the signature we wait
+/// for it instead, as asking again could credit twice. The balance only counts while the status is unknown, as
+/// unrelated transfers change it too. Rate limits are retried with an exponential backoff.
+/// Gives up once `timeout` has passed.
+pub fn verified_airdrop(
+    chain: &dyn Chain,
+    to: &Pubkey,
+    amount: u64,
+    timeout: Duration,
+) -> Result<AirdropReport, Error> {
+    let start = Instant::now();
+    let time_left = || timeout.saturating_sub(start.elapsed());
+    // Each attempt gets the same share of the timeout, so all of them can happen.
+    let attempt_timeout = timeout / MAX_ATTEMPTS;
+    let before = chain.balance(to)?;
+    let mut backoff = RATE_LIMIT_BACKOFF;
+    let mut last_signature = None;
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        let signature = match chain.request_airdrop(to, amount) {
+            Ok(signature) => signature,
+            Err(Error::AirdropFailed(e)) if is_rate_limited(&e.to_string()) && attempt < MAX_ATTEMPTS => {
+                if time_left() == Duration::ZERO {
+                    return Err(Error::AirdropNotCredited { signature: last_signature, attempts: attempt });
+                }
+                let wait = backoff.min(time_left());
+                eprintln!("The faucet is rate limiting us, retrying in {}s", wait.as_secs());
+                thread::sleep(wait);
+                backoff *= 2;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        last_signature = Some(signature);
+        let requested = Instant::now();
+        let report =
+            |credited, balance| AirdropReport { signature, requested: amount, credited, balance, attempts: attempt };
+
+        loop {
+            if time_left() == Duration::ZERO {
+                return Err(Error::AirdropNotCredited { signature: last_signature, attempts: attempt });
+            }
+            thread::sleep(POLL_INTERVAL.min(time_left()));
+            match chain.status(&signature)? {
+                Some(status) if status.err.is_some() => break,
+                Some(status) if status.committed => return Ok(report(amount, chain.balance(to)?)),
+                // It's on its way, only the timeout makes us stop waiting for it.
+                Some(_) => {}
+                None => {
+                    // Some faucets' signatures can't be looked up, then only the balance tells.
+                    let balance = chain.balance(to)?;
+                    if balance >= before.saturating_add(amount) {
+                        return Ok(report(balance - before, balance));
+                    }
+                    if requested.elapsed() >= attempt_timeout {
+                        break;
+                    }
+                }
+            }
+        }
+        eprintln!("Airdrop {} didn't arrive, requesting again", signature);
+    }
+    Err(Error::AirdropNotCredited { signature: last_signature, attempts: MAX_ATTEMPTS })
+}
+
+fn is_rate_limited(e: &str) -> bool {
+    let e = e.to_lowercase();
+    e.contains("429") || e.contains("too many requests") || e.contains("rate limit")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use solana_faucet::faucet::run_local_faucet;
+    use solana_sdk::native_token::LAMPORTS_PER_SOL;
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::signature::{Keypair, Signer};
+    use solana_streamer::socket::SocketAddrSpace;
+    use solana_test_validator::TestValidator;
+
+    use crate::airdrop::verified_airdrop;
+    use crate::chain::Chain;
+    use crate::mock::{AirdropFailure, MockChain};
+    use crate::Error;
+
+    #[test]
+    fn test_verified_airdrop() {
+        let mint = Keypair::new();
+        let mint_pubkey = mint.pubkey();
+        let faucet_addr = run_local_faucet(mint, None);
+        let testnet = TestValidator::with_no_fees(mint_pubkey, Some(faucet_addr), SocketAddrSpace::Unspecified);
+        let rpc_client = testnet.get_rpc_client();
+
+        let to = Pubkey::new_unique();
+        let amount = 3 * LAMPORTS_PER_SOL / 2;
+        let report = verified_airdrop(&rpc_client, &to, amount, Duration::from_secs(60)).unwrap();
+        assert_eq!(report.requested, amount);
+        assert_eq!(report.credited, amount);
+        assert_eq!(report.balance, amount);
+        assert_eq!(rpc_client.get_balance(&to).unwrap(), amount);
+
+        let report = verified_airdrop(&rpc_client, &to, amount, Duration::from_secs(60)).unwrap();
+        assert_eq!(report.credited, amount);
+        assert_eq!(report.balance, 2 * amount);
+    }
+
+    #[test]
+    fn test_airdrop_retries() {
+        let to = Pubkey::new_unique();
+        // A second each to show up, plenty for the mock.
+        let timeout = Duration::from_secs(5);
+        for failure in [AirdropFailure::Lost, AirdropFailure::Failed] {
+            let chain = MockChain::new(0);
+            chain.fail_airdrops(&[failure]);
+            let report = verified_airdrop(&chain, &to, 42, timeout).unwrap();
+            assert_eq!((report.attempts, report.credited, report.balance), (2, 42, 42), "{:?}", failure);
+        }
+
+        // Waits 2s and then 4s before asking again.
+        let chain = MockChain::new(0);
+        chain.fail_airdrops(&[AirdropFailure::RateLimited, AirdropFailure::RateLimited]);
+        let report = verified_airdrop(&chain, &to, 42, Duration::from_secs(30)).unwrap();
+        assert_eq!((report.attempts, report.credited), (3, 42));
+
+        // Every attempt gets its share of the timeout.
+        let chain = MockChain::new(0);
+        chain.fail_airdrops(&[AirdropFailure::Lost; 5]);
+        let result = verified_airdrop(&chain, &to, 42, timeout);
+        assert!(matches!(result, Err(Error::AirdropNotCredited { signature: Some(_), attempts: 5 })));
+        assert_eq!(chain.balance(&to).unwrap(), 0);
+    }
+}
@@ -0,0 +1,346 @@
+//! A tamper-evident local log of every protocol action.
+//!
+//! Every action is a JSON line holding the hash of the line before it, so changing, removing or reordering an entry
+//! breaks the chain from there on. Only public data is logged: session IDs, message hashes, keys and amounts.
+
+use std::env;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ring::digest::{digest, SHA256};
+use serde_json::{json, Value};
+use solana_sdk::hash::hash;
+use solana_sdk::message::Message;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::system_instruction::SystemInstruction;
+use solana_sdk::system_program;
+
+use crate::serialization::{AggMessage1, Serialize};
+use crate::Error;
+
+/// The directory holding the local state, `~/.solana-tss` if it isn't set.
+const HOME_VAR: &str = "SOLANA_TSS_HOME";
+/// What the first entry points back to.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+const COLUMNS: [&str; 10] =
+    ["seq", "time", "action", "session", "message_hash", "participants", "amount", "recipients", "prev", "hash"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    StepOne,
+    PartialSignature,
+    Aggregation,
+    Broadcast,
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::StepOne => "step_one",
+            Self::PartialSignature => "partial_signature",
+            Self::Aggregation => "aggregation",
+            Self::Broadcast => "broadcast",
+        })
+    }
+}
+
+/// A single protocol action, before it's chained into the log.
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub action: Action,
+    pub session: String,
+    pub message_hash: Option<String>,
+    pub participants: Vec<Pubkey>,
+    pub amount: u64,
+    pub recipients: Vec<Pubkey>,
+}
+
+impl Event {
+    pub fn new(action: Action, session: impl Into<String>, participants: Vec<Pubkey>) -> Self {
+        Self { action, session: session.into(), message_hash: None, participants, amount: 0, recipients: Vec::new() }
+    }
+
+    /// Record the hash of the message the action is about, and the transfers it makes.
+    pub fn with_message(mut self, message: &Message) -> Self {
+        self.message_hash = Some(message_hash(message));
+        for instruction in &message.instructions {
+            if message.account_keys[usize::from(instruction.program_id_index)] != system_program::id() {
+                continue;
+            }
+            if let Ok(SystemInstruction::Transfer { lamports }) = bincode::deserialize(&instruction.data) {
+                self.amount = self.amount.saturating_add(lamports);
+                if let Some(&to) = instruction.accounts.get(1) {
+                    self.recipients.push(message.account_keys[usize::from(to)]);
+                }
+            }
+        }
+        self
+    }
+}
+
+/// The session ID of a party's step one, derived from its public nonces so that step two can refer to it.
+pub fn nonce_session(message: &AggMessage1) -> String {
+    hex::encode(&digest(&SHA256, message.serialize_bs58().as_bytes()).as_ref()[..16])
+}
+
+/// The hash of a message, the same for every party signing it whatever the signatures it carries so far.
+pub fn message_hash(message: &Message) -> String {
+    hash(&message.serialize()).to_string()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => Err(Error::WrongExportFormat(s.to_string())),
+        }
+    }
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// The log in the `home` directory, see [`home`].
+    pub fn in_home(home: &Path) -> Self {
+        Self::new(home.join("audit.log"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Chain `event` to the end of the log, the log and its directory are created if needed.
+    /// The log is locked from reading the last entry until the new one is written, as the cosigner and the CLI can
+    /// append to the same log at the same time.
+    pub fn append(&self, event: &Event) -> Result<(), Error> {
+        let failed = |e| Error::FileFailed(self.path.clone(), e);
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(failed)?;
+        }
+        let mut options = OpenOptions::new();
+        options.read(true).append(true).create(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&self.path).map_err(failed)?;
+        lock_exclusive(&file).map_err(failed)?;
+        let mut log = String::new();
+        file.read_to_string(&mut log).map_err(failed)?;
+
+        let (seq, prev) = match parse_entries(&log)?.last() {
+            Some(last) => {
+                (last["seq"].as_u64().map_or(0, |seq| seq + 1), last["hash"].as_str().unwrap_or(GENESIS).to_string())
+            }
+            None => (0, GENESIS.to_string()),
+        };
+        let mut entry = json!({
+            "seq": seq,
+            "time": SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()),
+            "action": event.action.to_string(),
+            "session": event.session,
+            "message_hash": event.message_hash,
+            "participants": event.participants.iter().map(Pubkey::to_string).collect::<Vec<_>>(),
+            "amount": event.amount,
+            "recipients": event.recipients.iter().map(Pubkey::to_string).collect::<Vec<_>>(),
+            "prev": prev,
+        });
+        entry["hash"] = Value::String(entry_hash(&entry));
+        // Closing the file releases the lock.
+        file.write_all(format!("{}\n", entry).as_bytes()).map_err(failed)
+    }
+
+    /// All the entries, a log that doesn't exist yet is empty.
+    pub fn entries(&self) -> Result<Vec<Value>, Error> {
+        let log = match fs::read_to_string(&self.path) {
+            Ok(log) => log,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::FileFailed(self.path.clone(), e)),
+        };
+        parse_entries(&log)
+    }
+
+    /// Check that every entry is unchanged and follows the one before it, returns the number of entries.
+    pub fn verify(&self) -> Result<usize, Error> {
+        let entries = self.entries()?;
+        let mut prev = GENESIS.to_string();
+        for (i, entry) in entries.iter().enumerate() {
+            let tampered = |reason: &str| Err(Error::AuditTampered { line: i + 1, reason: reason.to_string() });
+            if entry["seq"].as_u64() != Some(i as u64) {
+                return tampered("out of sequence");
+            }
+            if entry["prev"].as_str() != Some(prev.as_str()) {
+                return tampered("doesn't follow the previous entry");
+            }
+            let hash = entry_hash(entry);
+            if entry["hash"].as_str() != Some(hash.as_str()) {
+                return tampered("the hash doesn't match the content");
+            }
+            prev = hash;
+        }
+        Ok(entries.len())
+    }
+}
+
+fn parse_entries(log: &str) -> Result<Vec<Value>, Error> {
+    log.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|e| Error::AuditTampered { line: i + 1, reason: e.to_string() })
+        })
+        .collect()
+}
+
+/// Wait until nobody else holds the lock of `file`, and take it until the file is closed.
+#[cfg(unix)]
+pub fn lock_exclusive(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    // Safety: the descriptor is open for as long as `file` lives.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn lock_exclusive(_: &File) -> io::Result<()> {
+    Ok(())
+}
+
+/// Render the entries as a JSON array, or as CSV with the keys separated by spaces.
+pub fn export(entries: &[Value], format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Json => format!("{}\n", Value::Array(entries.to_vec())),
+        ExportFormat::Csv => {
+            let mut csv = format!("{}\n", COLUMNS.join(","));
+            for entry in entries {
+                let row: Vec<_> = COLUMNS.iter().map(|column| csv_field(&entry[*column])).collect();
+                csv.push_str(&row.join(","));
+                csv.push('\n');
+            }
+            csv
+        }
+    }
+}
+
+fn csv_field(value: &Value) -> String {
+    let field = match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(values) => {
+            values.iter().map(|value| value.as_str().unwrap_or_default()).collect::<Vec<_>>().join(" ")
+        }
+        other => other.to_string(),
+    };
+    if field.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+/// The hash of everything in the entry but the hash itself, keys are serialized in sorted order.
+fn entry_hash(entry: &Value) -> String {
+    let mut body = entry.clone();
+    if let Some(fields) = body.as_object_mut() {
+        fields.remove("hash");
+    }
+    hex::encode(digest(&SHA256, body.to_string().as_bytes()))
+}
+
+/// The directory holding the local state.
+pub fn home() -> Result<PathBuf, Error> {
+    match env::var_os(HOME_VAR) {
+        Some(home) => Ok(PathBuf::from(home)),
+        None => env::var_os("HOME").map(|home| Path::new(&home).join(".solana-tss")).ok_or(Error::NoHomeDirectory),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::thread;
+
+    use solana_sdk::hash::Hash;
+    use solana_sdk::pubkey::Pubkey;
+
+    use crate::audit::{export, Action, AuditLog, Event, ExportFormat};
+    use crate::{create_unsigned_transaction, Error};
+
+    #[test]
+    fn test_audit_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let log = AuditLog::new(path.clone());
+        assert_eq!(log.verify().unwrap(), 0);
+
+        let (payer, to) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut tx = create_unsigned_transaction(1000, &to, Some("a, \"quoted\" memo".to_string()), &payer);
+        tx.message.recent_blockhash = Hash::new_unique();
+        let keys = vec![Pubkey::new_unique(), Pubkey::new_unique()];
+        log.append(&Event::new(Action::StepOne, "abcd", vec![keys[0]])).unwrap();
+        log.append(&Event::new(Action::PartialSignature, "abcd", keys.clone()).with_message(&tx.message)).unwrap();
+        log.append(&Event::new(Action::Aggregation, "sig", keys).with_message(&tx.message)).unwrap();
+        assert_eq!(log.verify().unwrap(), 3);
+
+        let entries = log.entries().unwrap();
+        assert_eq!(entries[1]["amount"], 1000);
+        assert_eq!(entries[1]["recipients"][0], to.to_string());
+        assert_eq!(entries[1]["message_hash"], entries[2]["message_hash"]);
+        let csv = export(&entries, ExportFormat::Csv);
+        assert_eq!(csv.lines().count(), 4);
+        assert!(csv.starts_with("seq,time,action,session,"));
+        assert!(csv.lines().nth(2).unwrap().contains(",partial_signature,abcd,"));
+
+        let original = fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = original.lines().collect();
+
+        // Changing an amount breaks that entry.
+        fs::write(&path, original.replacen("\"amount\":1000", "\"amount\":1", 1)).unwrap();
+        assert!(matches!(log.verify(), Err(Error::AuditTampered { line: 2, .. })));
+
+        // Dropping an entry breaks the one after it.
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        assert!(matches!(log.verify(), Err(Error::AuditTampered { line: 2, .. })));
+
+        // Truncating the end can't be detected from the log alone, but what's left is consistent.
+        fs::write(&path, format!("{}\n", lines[0])).unwrap();
+        assert_eq!(log.verify().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_concurrent_appends() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let log = AuditLog::new(path.clone());
+                thread::spawn(move || {
+                    for _ in 0..10 {
+                        log.append(&Event::new(Action::StepOne, writer.to_string(), Vec::new())).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(AuditLog::new(path.clone()).verify().unwrap(), 80);
+    }
+}
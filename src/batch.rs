@@ -0,0 +1,154 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use solana_sdk::message::Message;
+use solana_sdk::packet::PACKET_DATA_SIZE;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::transaction::Transaction;
+
+use crate::cli::parse_sol;
+use crate::{batch_instructions, Error};
+
+/// A single transfer of `amount` lamports to `to`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Payment {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_support::display_fromstr"))]
+    pub to: Pubkey,
+    pub amount: u64,
+}
+
+impl FromStr for Payment {
+    type Err = Error;
+    /// Parse `<ADDRESS>=<AMOUNT>`, where the amount is in SOL.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (to, amount) = s.split_once('=').ok_or_else(|| Error::InvalidPayment(s.to_string()))?;
+        let to = to.trim().parse().map_err(|_| Error::InvalidPayment(s.to_string()))?;
+        Ok(Payment { to, amount: parse_sol(amount.trim())? })
+    }
+}
+
+/// Read payments from a CSV file with an `address,amount` line per payment, the amount is in SOL.
+/// Empty lines, lines starting with `#` and an `address,amount` header are ignored.
+pub fn read_csv(path: &Path) -> Result<Vec<Payment>, Error> {
+    let content = fs::read_to_string(path).map_err(|e| Error::FileFailed(path.to_path_buf(), e))?;
+    parse_csv(&content)
+}
+
+fn parse_csv(content: &str) -> Result<Vec<Payment>, Error> {
+    let mut payments = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (i == 0 && line.eq_ignore_ascii_case("address,amount")) {
+            continue;
+        }
+        let payment = line.replacen(',', "=", 1).parse().map_err(|e| Error::InvalidCsvLine(i + 1, Box::new(e)))?;
+        payments.push(payment);
+    }
+    if payments.is_empty() {
+        return Err(Error::NoPayments);
+    }
+    Ok(payments)
+}
+
+/// Split the payments into as few transactions as possible, keeping their order.
+/// Every transaction pays from `from`, fits into a single packet once it's signed by `from` and the `fee_payer` if
+/// there is one, and repeats the memo if there is one.
+/// This is deterministic, so all parties end up with the same plan given the same payments.
+pub fn plan(
+    payments: &[Payment],
+    memo: Option<&str>,
+    from: &Pubkey,
+    fee_payer: Option<&Pubkey>,
+) -> Result<Vec<Vec<Payment>>, Error> {
+    if payments.is_empty() {
+        return Err(Error::NoPayments);
+    }
+    let fits = |batch: &[Payment]| {
+        let instructions = batch_instructions(batch, memo.map(str::to_string), from);
+        let tx = Transaction::new_unsigned(Message::new(&instructions, Some(fee_payer.unwrap_or(from))));
+        bincode::serialized_size(&tx).map_or(false, |size| size <= PACKET_DATA_SIZE as u64)
+    };
+
+    let mut batches: Vec<Vec<Payment>> = vec![Vec::new()];
+    for payment in payments {
+        let current = batches.last_mut().unwrap();
+        current.push(*payment);
+        if !fits(current) {
+            current.pop();
+            if current.is_empty() {
+                return Err(Error::TransactionTooLarge);
+            }
+            batches.push(vec![*payment]);
+            if !fits(batches.last().unwrap()) {
+                return Err(Error::TransactionTooLarge);
+            }
+        }
+    }
+    Ok(batches)
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::native_token::LAMPORTS_PER_SOL;
+    use solana_sdk::packet::PACKET_DATA_SIZE;
+    use solana_sdk::pubkey::Pubkey;
+
+    use crate::batch::{parse_csv, plan, Payment};
+    use crate::{create_batch_transaction, Error};
+
+    #[test]
+    fn test_parse_payment() {
+        let to = Pubkey::new_unique();
+        let payment: Payment = format!("{}=1.25", to).parse().unwrap();
+        assert_eq!(payment, Payment { to, amount: 5 * LAMPORTS_PER_SOL / 4 });
+        assert!(matches!(format!("{}", to).parse::<Payment>(), Err(Error::InvalidPayment(_))));
+        assert!(matches!("abc=1".parse::<Payment>(), Err(Error::InvalidPayment(_))));
+        assert!(matches!(format!("{}=0.1234567891", to).parse::<Payment>(), Err(Error::ExcessPrecision(_))));
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let csv = format!("address,amount\n{}, 1\n\n# comment\n{},0.5\n", a, b);
+        let payments = parse_csv(&csv).unwrap();
+        assert_eq!(
+            payments,
+            vec![Payment { to: a, amount: LAMPORTS_PER_SOL }, Payment { to: b, amount: LAMPORTS_PER_SOL / 2 }]
+        );
+        assert!(matches!(parse_csv(&format!("{},1\n{}\n", a, b)), Err(Error::InvalidCsvLine(2, _))));
+        assert!(matches!(parse_csv("address,amount\n"), Err(Error::NoPayments)));
+    }
+
+    #[test]
+    fn test_plan() {
+        let payer = Pubkey::new_unique();
+        let memo = Some("payouts");
+        for n in [1, 2, 20, 21, 100] {
+            let payments: Vec<_> = (0..n).map(|i| Payment { to: Pubkey::new_unique(), amount: i + 1 }).collect();
+            let batches = plan(&payments, memo, &payer, None).unwrap();
+            // Nothing got lost or reordered.
+            assert_eq!(batches.concat(), payments);
+            for (i, batch) in batches.iter().enumerate() {
+                let tx = create_batch_transaction(batch, memo.map(str::to_string), &payer);
+                assert!(bincode::serialized_size(&tx).unwrap() <= PACKET_DATA_SIZE as u64);
+                // Every batch but the last one is full.
+                if i + 1 < batches.len() {
+                    let mut bigger = batch.clone();
+                    bigger.push(batches[i + 1][0]);
+                    let tx = create_batch_transaction(&bigger, memo.map(str::to_string), &payer);
+                    assert!(bincode::serialized_size(&tx).unwrap() > PACKET_DATA_SIZE as u64);
+                }
+            }
+        }
+        assert!(matches!(plan(&[], None, &payer, None), Err(Error::NoPayments)));
+
+        // A separate fee payer adds a signature and an account, so fewer payments fit.
+        let payments: Vec<_> = (0..20).map(|i| Payment { to: Pubkey::new_unique(), amount: i + 1 }).collect();
+        let fee_payer = Pubkey::new_unique();
+        let batches = plan(&payments, memo, &payer, Some(&fee_payer)).unwrap();
+        assert_eq!(batches.concat(), payments);
+        assert!(batches.len() > plan(&payments, memo, &payer, None).unwrap().len());
+    }
+}
@@ -0,0 +1,165 @@
+//! Every interaction with the cluster goes through [`Chain`], so the commands can run against a mock in tests.
+
+use std::collections::HashMap;
+
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::RpcSendTransactionConfig;
+use solana_sdk::clock::Slot;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::message::Message;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{Transaction, TransactionError};
+
+use crate::rpc::Rpc;
+use crate::simulate::{self, Simulation};
+use crate::watch;
+use crate::Error;
+
+/// Where a transaction is at, as far as the cluster knows.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Status {
+    pub slot: Slot,
+    pub err: Option<TransactionError>,
+    /// Whether it reached the commitment level the chain was configured with.
+    pub committed: bool,
+}
+
+pub trait Chain {
+    fn balance(&self, address: &Pubkey) -> Result<u64, Error>;
+    fn request_airdrop(&self, to: &Pubkey, lamports: u64) -> Result<Signature, Error>;
+    fn latest_blockhash(&self) -> Result<Hash, Error>;
+    /// The last block height at which transactions using `hash` can land, or a later one,
+    /// `None` if the node doesn't know the hash, which can be because it's expired or because the node is behind.
+    fn last_valid_block_height(&self, hash: &Hash) -> Result<Option<u64>, Error>;
+    /// Once this is past the last valid block height of a hash, no fork can include a transaction using it.
+    fn finalized_block_height(&self) -> Result<u64, Error>;
+    /// Send without preflight checks, callers simulate first.
+    fn send(&self, tx: &Transaction) -> Result<Signature, Error>;
+    /// The status of a transaction, `None` if the cluster never saw it.
+    fn status(&self, signature: &Signature) -> Result<Option<Status>, Error>;
+    fn simulate(&self, tx: &Transaction, sig_verify: bool) -> Result<Simulation, Error>;
+    fn fee_for_message(&self, message: &Message) -> Result<u64, Error>;
+    fn minimum_balance_for_rent_exemption(&self, data_len: usize) -> Result<u64, Error>;
+    /// The amount of every token account owned by `owner`.
+    fn token_amounts(&self, owner: &Pubkey) -> Result<HashMap<Pubkey, u64>, Error>;
+}
+
+impl Chain for RpcClient {
+    fn balance(&self, address: &Pubkey) -> Result<u64, Error> {
+        self.get_balance(address).map_err(Error::BalaceFailed)
+    }
+
+    fn request_airdrop(&self, to: &Pubkey, lamports: u64) -> Result<Signature, Error> {
+        RpcClient::request_airdrop(self, to, lamports).map_err(Error::AirdropFailed)
+    }
+
+    fn latest_blockhash(&self) -> Result<Hash, Error> {
+        self.get_latest_blockhash().map_err(Error::RecentHashFailed)
+    }
+
+    fn last_valid_block_height(&self, hash: &Hash) -> Result<Option<u64>, Error> {
+        let processed = CommitmentConfig::processed();
+        if !RpcClient::is_blockhash_valid(self, hash, processed).map_err(Error::ConfirmingTransactionFailed)? {
+            return Ok(None);
+        }
+        // Asked after the check, the latest hash is at least as new as `hash`, so `hash` can't outlive it.
+        let (_, last_valid) = self.get_latest_blockhash_with_commitment(processed).map_err(Error::RecentHashFailed)?;
+        Ok(Some(last_valid))
+    }
+
+    fn finalized_block_height(&self) -> Result<u64, Error> {
+        self.get_block_height_with_commitment(CommitmentConfig::finalized()).map_err(Error::ConfirmingTransactionFailed)
+    }
+
+    fn send(&self, tx: &Transaction) -> Result<Signature, Error> {
+        let config = RpcSendTransactionConfig { skip_preflight: true, ..RpcSendTransactionConfig::default() };
+        self.send_transaction_with_config(tx, config).map_err(Error::SendTransactionFailed)
+    }
+
+    fn status(&self, signature: &Signature) -> Result<Option<Status>, Error> {
+        let status = self
+            .get_signature_statuses_with_history(&[*signature])
+            .map_err(Error::ConfirmingTransactionFailed)?
+            .value
+            .pop()
+            .flatten();
+        Ok(status.map(|status| Status {
+            committed: status.satisfies_commitment(self.commitment()),
+            slot: status.slot,
+            err: status.err,
+        }))
+    }
+
+    fn simulate(&self, tx: &Transaction, sig_verify: bool) -> Result<Simulation, Error> {
+        simulate::simulate(self, tx, sig_verify)
+    }
+
+    fn fee_for_message(&self, message: &Message) -> Result<u64, Error> {
+        self.get_fee_for_message(message).map_err(Error::FeeFailed)
+    }
+
+    fn minimum_balance_for_rent_exemption(&self, data_len: usize) -> Result<u64, Error> {
+        self.get_minimum_balance_for_rent_exemption(data_len).map_err(Error::RentFailed)
+    }
+
+    fn token_amounts(&self, owner: &Pubkey) -> Result<HashMap<Pubkey, u64>, Error> {
+        let config = watch::token_accounts_config(owner, self.commitment());
+        let accounts = self
+            .get_program_accounts_with_config(&watch::token_program(), config)
+            .map_err(Error::TokenAccountsFailed)?;
+        let amounts = accounts
+            .into_iter()
+            .filter_map(|(address, account)| watch::token_account(&account.data).map(|(_, amount)| (address, amount)));
+        Ok(amounts.collect())
+    }
+}
+
+/// Fails over between the endpoints, and asks for a quorum on the reads that support it.
+impl Chain for Rpc {
+    fn balance(&self, address: &Pubkey) -> Result<u64, Error> {
+        self.get_balance(address)
+    }
+
+    fn request_airdrop(&self, to: &Pubkey, lamports: u64) -> Result<Signature, Error> {
+        self.failover(|client| Chain::request_airdrop(client, to, lamports))
+    }
+
+    fn latest_blockhash(&self) -> Result<Hash, Error> {
+        self.get_latest_blockhash()
+    }
+
+    fn last_valid_block_height(&self, hash: &Hash) -> Result<Option<u64>, Error> {
+        self.failover(|client| Chain::last_valid_block_height(client, hash))
+    }
+
+    /// An endpoint that lags behind only reports a lower height, which delays the expiry but never hastens it.
+    fn finalized_block_height(&self) -> Result<u64, Error> {
+        self.failover(RpcClient::finalized_block_height)
+    }
+
+    fn send(&self, tx: &Transaction) -> Result<Signature, Error> {
+        self.failover(|client| Chain::send(client, tx))
+    }
+
+    fn status(&self, signature: &Signature) -> Result<Option<Status>, Error> {
+        self.failover(|client| Chain::status(client, signature))
+    }
+
+    fn simulate(&self, tx: &Transaction, sig_verify: bool) -> Result<Simulation, Error> {
+        self.failover(|client| Chain::simulate(client, tx, sig_verify))
+    }
+
+    fn fee_for_message(&self, message: &Message) -> Result<u64, Error> {
+        self.failover(|client| Chain::fee_for_message(client, message))
+    }
+
+    fn minimum_balance_for_rent_exemption(&self, data_len: usize) -> Result<u64, Error> {
+        self.failover(|client| Chain::minimum_balance_for_rent_exemption(client, data_len))
+    }
+
+    fn token_amounts(&self, owner: &Pubkey) -> Result<HashMap<Pubkey, u64>, Error> {
+        self.failover(|client| Chain::token_amounts(client, owner))
+    }
+}
@@ -1,14 +1,32 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
+use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::hash::Hash;
-use solana_sdk::{pubkey::Pubkey, signature::Keypair};
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
+use solana_sdk::transaction::Transaction;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+};
 
+use crate::audit::ExportFormat;
+use crate::batch::{self, Payment};
 use crate::error::Error;
-use crate::serialization::{AggMessage1, PartialSignature, SecretAggStepOne, Serialize};
+use crate::keystore;
+use crate::rpc::Rpc;
+use crate::serialization::{AggMessage1, Encoding, PartialSignature, SecretAggStepOne, Serialize};
+use crate::wallet::Proof;
+use crate::{verify, wire};
 
+const SOL_DECIMALS: usize = 9;
+
+// This intentionally doesn't derive `Debug`, as it holds secret keys.
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug, Parser)]
+#[derive(Parser)]
 #[clap(about, version, author)]
 pub enum Options {
     /// Generate a pair of keys.
@@ -19,9 +37,8 @@ pub enum Options {
     Balance {
         /// The address to check the balance of
         address: Pubkey,
-        /// Choose the desired network: Mainnet/Testnet/Devnet
-        #[clap(default_value = "testnet", long)]
-        net: Network,
+        #[clap(flatten)]
+        connection: Connection,
     },
     /// Request an airdrop from a faucet.
     #[clap(display_order = 3)]
@@ -29,12 +46,13 @@ pub enum Options {
         /// Address of the recipient
         #[clap(long)]
         to: Pubkey,
-        /// The amount of SOL you want to send.
+        #[clap(flatten)]
+        amount: Amount,
+        #[clap(flatten)]
+        connection: Connection,
+        /// Print the transaction ID without checking that the airdrop arrived
         #[clap(long)]
-        amount: f64,
-        /// Choose the desired network: Mainnet/Testnet/Devnet
-        #[clap(default_value = "testnet", long)]
-        net: Network,
+        no_wait: bool,
     },
     /// Send a transaction using a single private key.
     #[clap(display_order = 4)]
@@ -42,25 +60,25 @@ pub enum Options {
         /// A Base58 secret key
         #[clap(parse(try_from_str = parse_keypair_bs58), long)]
         keypair: Keypair,
-        /// The amount of SOL you want to send.
-        #[clap(long)]
-        amount: f64,
+        #[clap(flatten)]
+        amount: Amount,
         /// Address of the recipient
         #[clap(long)]
         to: Pubkey,
-        /// Choose the desired network: Mainnet/Testnet/Devnet
-        #[clap(default_value = "testnet", long)]
-        net: Network,
+        #[clap(flatten)]
+        connection: Connection,
         /// Add a memo to the transaction
         #[clap(long)]
         memo: Option<String>,
+        /// Print the transaction ID without waiting for it to land
+        #[clap(long)]
+        no_wait: bool,
     },
     /// Print the hash of a recent block, can be used to pass to the `agg-send` steps
     #[clap(display_order = 8)]
     RecentBlockHash {
-        /// Choose the desired network: Mainnet/Testnet/Devnet
-        #[clap(default_value = "testnet", long)]
-        net: Network,
+        #[clap(flatten)]
+        connection: Connection,
     },
     /// Aggregate a list of addresses into a single address that they can all sign on together
     #[clap(display_order = 5)]
@@ -68,6 +86,27 @@ pub enum Options {
         /// List of addresses
         #[clap(min_values = 2, required = true)]
         keys: Vec<Pubkey>,
+        /// Use the address derived from the aggregate key by this label instead, e.g. one per customer
+        #[clap(long)]
+        label: Option<String>,
+    },
+    /// Split a list of payments into as few transactions as possible, each one is signed in its own round
+    #[clap(display_order = 7)]
+    PlanBatch {
+        #[clap(flatten)]
+        recipients: Recipients,
+        /// Add a memo to every transaction
+        #[clap(long)]
+        memo: Option<String>,
+        /// List of addresses
+        #[clap(long, required = true, min_values = 2)]
+        keys: Vec<Pubkey>,
+        /// Use the address derived from the aggregate key by this label instead, e.g. one per customer
+        #[clap(long)]
+        label: Option<String>,
+        /// Pay the fee from this address instead of the aggregate address, it has to sign the transaction too
+        #[clap(long)]
+        fee_payer: Option<Pubkey>,
     },
     /// Start aggregate signing
     #[clap(display_order = 6)]
@@ -75,20 +114,22 @@ pub enum Options {
         /// A Base58 secret key of the party signing
         #[clap(parse(try_from_str = parse_keypair_bs58))]
         keypair: Keypair,
+        /// The encoding of the printed messages: base58/base64/hex/json
+        #[clap(default_value = "base58", long)]
+        encoding: Encoding,
     },
     /// Step 2 of aggregate signing, you should pass in the secret data from step 1.
-    /// It's important that all parties pass in exactly the same transaction details (amount,to,net,memo,recent_block_hash)
+    /// It's important that all parties pass in exactly the same transaction details (amount,to,pay,batch,memo,recent_block_hash)
     #[clap(display_order = 9)]
     AggSendStepTwo {
         /// A Base58 secret key of the party signing
         #[clap(parse(try_from_str = parse_keypair_bs58), long)]
         keypair: Keypair,
-        /// The amount of SOL you want to send.
-        #[clap(long)]
-        amount: f64,
-        /// Address of the recipient
-        #[clap(long)]
-        to: Pubkey,
+        #[clap(flatten)]
+        recipients: Recipients,
+        /// Which transaction of the payout plan to sign, see `plan-batch`
+        #[clap(long, default_value = "0")]
+        batch: usize,
         /// Add a memo to the transaction
         #[clap(long)]
         memo: Option<String>,
@@ -98,41 +139,576 @@ pub enum Options {
         /// List of addresses that are part of this
         #[clap(long, required = true, min_values = 2)]
         keys: Vec<Pubkey>,
+        /// Use the address derived from the aggregate key by this label instead, e.g. one per customer
+        #[clap(long)]
+        label: Option<String>,
+        /// Pay the fee from this address instead of the aggregate address, it has to sign the transaction too
+        #[clap(long)]
+        fee_payer: Option<Pubkey>,
         /// A list of all the first messages received in step 1
-        #[clap(long, required = true, min_values = 1, forbid_empty_values = true, parse(try_from_str = Serialize::deserialize_bs58))]
+        #[clap(long, required = true, min_values = 1, forbid_empty_values = true, parse(try_from_str = Serialize::decode))]
         first_messages: Vec<AggMessage1>,
         /// The secret state received in step 2.
-        #[clap(long, forbid_empty_values = true, parse(try_from_str = Serialize::deserialize_bs58))]
+        #[clap(long, forbid_empty_values = true, parse(try_from_str = Serialize::decode))]
         secret_state: SecretAggStepOne,
+        /// The encoding of the printed partial signature: base58/base64/hex/json
+        #[clap(default_value = "base58", long)]
+        encoding: Encoding,
+        /// Check the balance and the fee, and simulate the transaction before signing, when `--net` or `--url` is given
+        #[clap(flatten)]
+        connection: Connection,
+        /// A JSON file with local rules the transaction has to follow before it's signed, see the README
+        #[clap(long)]
+        policy: Option<PathBuf>,
+        /// Sign without asking for confirmation after printing the decoded transaction
+        #[clap(long)]
+        yes: bool,
+        /// Sign as the aggregate key of these addresses instead, when it's another signer of the transaction like the fee payer
+        #[clap(long, min_values = 2)]
+        sign_as: Vec<Pubkey>,
     },
     /// Aggregate all the partial signatures together into a full signature, and send the transaction to Solana
     #[clap(display_order = 10)]
     AggregateSignaturesAndBroadcast {
         // A list of all partial signatures produced in step three.
-        #[clap(long, required = true, min_values = 2, forbid_empty_values = true, parse(try_from_str = Serialize::deserialize_bs58))]
+        #[clap(long, required = true, min_values = 2, forbid_empty_values = true, parse(try_from_str = Serialize::decode))]
         signatures: Vec<PartialSignature>,
-        /// The amount of SOL you want to send.
+        #[clap(flatten)]
+        recipients: Recipients,
+        /// Which transaction of the payout plan to sign, see `plan-batch`
+        #[clap(long, default_value = "0")]
+        batch: usize,
+        /// Add a memo to the transaction
+        #[clap(long, forbid_empty_values = true)]
+        memo: Option<String>,
+        /// A hash of a recent block, can be obtained by calling `recent-block-hash`, all parties *must* pass in the same hash.
         #[clap(long)]
-        amount: f64,
-        /// Address of the recipient
+        recent_block_hash: Hash,
+        #[clap(flatten)]
+        connection: Connection,
+        /// List of addresses
+        #[clap(long, required = true, min_values = 2)]
+        keys: Vec<Pubkey>,
+        /// Use the address derived from the aggregate key by this label instead, e.g. one per customer
         #[clap(long)]
-        to: Pubkey,
+        label: Option<String>,
+        /// Pay the fee from this address instead of the aggregate address, it has to sign the transaction too
+        #[clap(long)]
+        fee_payer: Option<Pubkey>,
+        /// A Base58 secret key of another signer, e.g. the fee payer
+        #[clap(long = "signer", parse(try_from_str = parse_keypair_bs58))]
+        signers: Vec<Keypair>,
+        /// Only simulate the signed transaction and print the logs, without sending it
+        #[clap(long)]
+        simulate_only: bool,
+        /// Print the transaction ID without waiting for it to land
+        #[clap(long, conflicts_with = "simulate-only")]
+        no_wait: bool,
+    },
+    /// Aggregate all the partial signatures together into a full signature, and print the signed transaction without sending it
+    #[clap(display_order = 11)]
+    AggregateSignatures {
+        // A list of all partial signatures produced in step three.
+        #[clap(long, required = true, min_values = 2, forbid_empty_values = true, parse(try_from_str = Serialize::decode))]
+        signatures: Vec<PartialSignature>,
+        #[clap(flatten)]
+        recipients: Recipients,
+        /// Which transaction of the payout plan to sign, see `plan-batch`
+        #[clap(long, default_value = "0")]
+        batch: usize,
         /// Add a memo to the transaction
         #[clap(long, forbid_empty_values = true)]
         memo: Option<String>,
         /// A hash of a recent block, can be obtained by calling `recent-block-hash`, all parties *must* pass in the same hash.
         #[clap(long)]
         recent_block_hash: Hash,
-        /// List of addresses that are part of this
-        /// Choose the desired network: Mainnet/Testnet/Devnet
-        #[clap(default_value = "testnet", long)]
-        net: Network,
         /// List of addresses
         #[clap(long, required = true, min_values = 2)]
         keys: Vec<Pubkey>,
+        /// Use the address derived from the aggregate key by this label instead, e.g. one per customer
+        #[clap(long)]
+        label: Option<String>,
+        /// Pay the fee from this address instead of the aggregate address, it has to sign the transaction too
+        #[clap(long)]
+        fee_payer: Option<Pubkey>,
+        /// The partial signatures are of the aggregate key of these addresses instead, when it's another signer like the fee payer
+        #[clap(long, min_values = 2)]
+        sign_as: Vec<Pubkey>,
+        /// A Base58 secret key of another signer, e.g. the fee payer
+        #[clap(long = "signer", parse(try_from_str = parse_keypair_bs58))]
+        signers: Vec<Keypair>,
+        /// Add the signatures to this partially signed transaction of the same session, instead of starting over
+        #[clap(long)]
+        file: Option<PathBuf>,
+        /// The encoding of the printed transaction: base58/base64/hex
+        #[clap(default_value = "base64", long)]
+        encoding: Encoding,
+        /// Write the raw transaction to this file instead of printing it
+        #[clap(long)]
+        out: Option<PathBuf>,
+    },
+    /// Send a signed transaction, as printed by `aggregate-signatures`, and wait for it to be confirmed
+    #[clap(display_order = 12)]
+    Broadcast {
+        /// The signed transaction in base58/base64/hex
+        #[clap(parse(try_from_str = wire::decode), required_unless_present = "file", conflicts_with = "file")]
+        transaction: Option<Transaction>,
+        /// A file holding the raw transaction, as written by `aggregate-signatures --out`
+        #[clap(long)]
+        file: Option<PathBuf>,
+        #[clap(flatten)]
+        connection: Connection,
+        /// Print the transaction ID without waiting for it to land
+        #[clap(long)]
+        no_wait: bool,
+    },
+    /// Encrypt a secret share into a keystore file, the password is taken from SOLANA_TSS_PASSWORD or read from stdin
+    #[clap(display_order = 13)]
+    EncryptShare {
+        /// A Base58 secret key of the party
+        #[clap(parse(try_from_str = parse_keypair_bs58))]
+        keypair: Keypair,
+        /// Where to write the keystore
+        #[clap(long)]
+        out: PathBuf,
+        /// PBKDF2 iterations, more is slower to unlock but harder to brute force
+        #[clap(long, default_value_t = keystore::DEFAULT_ITERATIONS)]
+        iterations: u32,
+    },
+    /// Run a signer that holds a share from a keystore, and signs the requests its policy allows over a local API
+    #[clap(display_order = 14)]
+    Cosigner {
+        /// The keystore holding the share, see `encrypt-share`
+        #[clap(long)]
+        keystore: PathBuf,
+        /// A JSON file with the spending policy
+        #[clap(long)]
+        policy: PathBuf,
+        /// The address to listen on
+        #[clap(long, default_value = "127.0.0.1:7227")]
+        listen: SocketAddr,
+        /// The file holding the API token, it's created if it doesn't exist. Defaults to the keystore path with a `.token` extension
+        #[clap(long)]
+        token_file: Option<PathBuf>,
+    },
+    /// Check or export the local audit log of protocol actions, kept in SOLANA_TSS_HOME (~/.solana-tss by default)
+    #[clap(display_order = 15)]
+    Audit {
+        #[clap(subcommand)]
+        command: AuditCommand,
+    },
+    /// Stream balance changes, transactions and token account updates of an address as they happen
+    #[clap(display_order = 16)]
+    Watch {
+        /// The address to watch, usually the aggregate address
+        address: Pubkey,
+        #[clap(flatten)]
+        connection: Connection,
+        /// The websocket endpoint, derived from the first RPC endpoint by default
+        #[clap(long)]
+        ws_url: Option<String>,
+        /// Print every event as a line of JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Sign BPF upgradeable loader operations with the aggregate key as the authority, start with `agg-send-step-one`
+    #[clap(display_order = 17)]
+    Program {
+        #[clap(subcommand)]
+        step: ProgramStep,
+    },
+    /// Add the signature of an ordinary keypair, e.g. the fee payer, to a partially signed transaction file
+    #[clap(display_order = 18)]
+    SignTransaction {
+        /// The partially signed transaction, as written by `aggregate-signatures --out`
+        #[clap(long)]
+        file: PathBuf,
+        /// A Base58 secret key of the signer
+        #[clap(parse(try_from_str = parse_keypair_bs58), long)]
+        keypair: Keypair,
+        /// Where to write the transaction, the input file by default
+        #[clap(long)]
+        out: Option<PathBuf>,
+        /// Sign without asking for confirmation after printing the decoded transaction
+        #[clap(long)]
+        yes: bool,
+    },
+    /// Check a signature of the aggregate address offline, on a signed transaction or on a message
+    #[clap(display_order = 19)]
+    Verify {
+        /// The aggregate address, instead of `--keys`
+        #[clap(long, required_unless_present = "keys")]
+        aggregate: Option<Pubkey>,
+        /// List of addresses
+        #[clap(long, min_values = 2, conflicts_with = "aggregate")]
+        keys: Vec<Pubkey>,
+        /// Use the address derived from the aggregate key by this label instead
+        #[clap(long, requires = "keys")]
+        label: Option<String>,
+        #[clap(flatten)]
+        signed: SignedMessage,
+        /// The Base58 signature of `--message`
+        #[clap(long, requires = "message", required_unless_present_any = &["transaction", "file"])]
+        signature: Option<Signature>,
+    },
+    /// Check one party's partial signature offline against its key, the nonces of every party and the message,
+    /// to find out whose output is wrong when the aggregated signature doesn't verify
+    #[clap(display_order = 20)]
+    VerifyPartial {
+        /// The partial signature to check
+        #[clap(long, forbid_empty_values = true, parse(try_from_str = Serialize::decode))]
+        signature: PartialSignature,
+        /// The address of the party that produced it
+        #[clap(long)]
+        signer: Pubkey,
+        /// List of addresses
+        #[clap(long, required = true, min_values = 2)]
+        keys: Vec<Pubkey>,
+        /// Use the address derived from the aggregate key by this label instead
+        #[clap(long)]
+        label: Option<String>,
+        /// The first messages of every party in step 1, the signer's included
+        #[clap(long, required = true, min_values = 2, forbid_empty_values = true, parse(try_from_str = Serialize::decode))]
+        first_messages: Vec<AggMessage1>,
+        #[clap(flatten)]
+        signed: SignedMessage,
+    },
+    /// Prove every party holds the share behind its key, before funding the aggregate address of a new key set
+    #[clap(display_order = 21)]
+    Setup {
+        #[clap(subcommand)]
+        step: SetupStep,
+    },
+}
+
+// This intentionally doesn't derive `Debug`, as it holds secret keys.
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand)]
+pub enum ProgramStep {
+    /// Step 2 of aggregate signing a loader operation, you should pass in the secret data from step 1
+    StepTwo {
+        /// A Base58 secret key of the party signing
+        #[clap(parse(try_from_str = parse_keypair_bs58), long)]
+        keypair: Keypair,
+        #[clap(flatten)]
+        session: ProgramSession,
+        /// A list of all the first messages received in step 1
+        #[clap(long, required = true, min_values = 1, forbid_empty_values = true, parse(try_from_str = Serialize::decode))]
+        first_messages: Vec<AggMessage1>,
+        /// The secret state received in step 1.
+        #[clap(long, forbid_empty_values = true, parse(try_from_str = Serialize::decode))]
+        secret_state: SecretAggStepOne,
+        /// The encoding of the printed partial signature: base58/base64/hex/json
+        #[clap(default_value = "base58", long)]
+        encoding: Encoding,
+        /// A JSON file with local rules the operation has to follow before it's signed, see the README
+        #[clap(long)]
+        policy: Option<PathBuf>,
+        /// Sign without asking for confirmation after printing the decoded transaction
+        #[clap(long)]
+        yes: bool,
+        #[clap(subcommand)]
+        operation: LoaderOperation,
+    },
+    /// Aggregate the partial signatures of a loader operation, add the other signatures, and send it to Solana
+    AggregateAndBroadcast {
+        /// A list of all partial signatures produced in step two.
+        #[clap(long, required = true, min_values = 2, forbid_empty_values = true, parse(try_from_str = Serialize::decode))]
+        signatures: Vec<PartialSignature>,
+        #[clap(flatten)]
+        session: ProgramSession,
+        /// A Base58 secret key of another signer: the fee payer, or the new program account when deploying
+        #[clap(long = "signer", parse(try_from_str = parse_keypair_bs58))]
+        signers: Vec<Keypair>,
+        #[clap(flatten)]
+        connection: Connection,
+        /// Print the transaction ID without waiting for it to land
+        #[clap(long)]
+        no_wait: bool,
+        #[clap(subcommand)]
+        operation: LoaderOperation,
     },
 }
 
+/// What all the parties have to agree on when signing a loader operation, besides the operation itself.
+#[derive(Debug, Args)]
+pub struct ProgramSession {
+    /// List of addresses
+    #[clap(long, required = true, min_values = 2)]
+    pub keys: Vec<Pubkey>,
+    /// Use the address derived from the aggregate key by this label instead
+    #[clap(long)]
+    pub label: Option<String>,
+    /// A hash of a recent block, all parties *must* pass in the same hash.
+    #[clap(long)]
+    pub recent_block_hash: Hash,
+    /// Pay the fees (and the rent when deploying) from this address instead of the aggregate address
+    #[clap(long)]
+    pub fee_payer: Option<Pubkey>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum LoaderOperation {
+    /// Write the next chunk of a program into a buffer whose authority is the aggregate address
+    Write {
+        #[clap(long)]
+        buffer: Pubkey,
+        /// The compiled program, the chunk that fits in a transaction is taken from it
+        #[clap(long)]
+        program_file: PathBuf,
+        /// Where in the program the chunk starts, the previous write prints the next one
+        #[clap(long, default_value = "0")]
+        offset: u32,
+    },
+    /// Deploy a new program from a buffer, the program address has to sign with `--signer` too
+    Deploy {
+        #[clap(long)]
+        program: Pubkey,
+        #[clap(long)]
+        buffer: Pubkey,
+        /// The largest size the program can be upgraded to, in bytes
+        #[clap(long)]
+        max_len: usize,
+    },
+    /// Replace a program with the content of a buffer
+    Upgrade {
+        #[clap(long)]
+        program: Pubkey,
+        #[clap(long)]
+        buffer: Pubkey,
+        /// Where the lamports of the buffer go, the fee payer by default
+        #[clap(long)]
+        spill: Option<Pubkey>,
+    },
+    /// Hand the upgrade authority of a program, or the authority of a buffer, to another address
+    SetAuthority {
+        #[clap(long, required_unless_present = "buffer", conflicts_with = "buffer")]
+        program: Option<Pubkey>,
+        #[clap(long)]
+        buffer: Option<Pubkey>,
+        #[clap(long, required_unless_present = "make-final")]
+        new_authority: Option<Pubkey>,
+        /// Make the program immutable, it can never be upgraded again
+        #[clap(long = "final", conflicts_with = "new-authority")]
+        make_final: bool,
+    },
+    /// Close a buffer and reclaim its lamports
+    CloseBuffer {
+        #[clap(long)]
+        buffer: Pubkey,
+        /// Where the lamports go, the fee payer by default
+        #[clap(long)]
+        recipient: Option<Pubkey>,
+    },
+}
+
+// This intentionally doesn't derive `Debug`, as it holds secret keys.
+#[derive(Subcommand)]
+pub enum SetupStep {
+    /// Sign the challenge of the key set, and send the printed proof to every other party
+    Prove {
+        /// A Base58 secret key of the party
+        #[clap(parse(try_from_str = parse_keypair_bs58), long)]
+        keypair: Keypair,
+        /// List of addresses
+        #[clap(long, required = true, min_values = 2)]
+        keys: Vec<Pubkey>,
+    },
+    /// Check the proofs of every party, and store the verified wallet so the signing commands accept its key set
+    Verify {
+        /// List of addresses
+        #[clap(long, required = true, min_values = 2)]
+        keys: Vec<Pubkey>,
+        /// The proof of every party, as printed by `setup prove`
+        #[clap(long, required = true, min_values = 2)]
+        proofs: Vec<Proof>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AuditCommand {
+    /// Check that no entry of the log was changed, removed or reordered
+    Verify,
+    /// Print every entry of the log
+    Export {
+        /// json/csv
+        #[clap(long, default_value = "json")]
+        format: ExportFormat,
+    },
+}
+
+impl Options {
+    /// Connect to the cluster, if the command talks to it.
+    pub fn rpc(&self) -> Result<Option<Rpc>, Error> {
+        match self {
+            Self::Balance { connection, .. }
+            | Self::Airdrop { connection, .. }
+            | Self::SendSingle { connection, .. }
+            | Self::RecentBlockHash { connection }
+            | Self::AggregateSignaturesAndBroadcast { connection, .. }
+            | Self::Broadcast { connection, .. }
+            | Self::Watch { connection, .. }
+            | Self::Program { step: ProgramStep::AggregateAndBroadcast { connection, .. } } => {
+                connection.rpc().map(Some)
+            }
+            Self::AggSendStepTwo { connection, .. } if connection.is_given() => connection.rpc().map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// An exact amount, either as a decimal number of SOL or as an integer number of lamports.
+#[derive(Debug, Args)]
+pub struct Amount {
+    /// The amount of SOL you want to send, at most 9 decimal places.
+    #[clap(long, parse(try_from_str = parse_sol), required_unless_present = "lamports", conflicts_with = "lamports")]
+    amount: Option<u64>,
+    /// The amount of lamports you want to send.
+    #[clap(long)]
+    lamports: Option<u64>,
+}
+
+impl Amount {
+    pub fn lamports(&self) -> u64 {
+        self.amount.or(self.lamports).expect("clap requires either --amount or --lamports")
+    }
+}
+
+/// Who gets paid, either a single recipient or a list of payments.
+#[derive(Debug, Args)]
+pub struct Recipients {
+    /// Address of the recipient
+    #[clap(long, required_unless_present_any = &["pay", "payments-csv"], conflicts_with_all = &["pay", "payments-csv"])]
+    to: Option<Pubkey>,
+    /// The amount of SOL you want to send, at most 9 decimal places.
+    #[clap(long, parse(try_from_str = parse_sol), requires = "to", conflicts_with = "lamports")]
+    amount: Option<u64>,
+    /// The amount of lamports you want to send.
+    #[clap(long, requires = "to")]
+    lamports: Option<u64>,
+    /// A payment as `<ADDRESS>=<AMOUNT>` with the amount in SOL, repeat it to pay many recipients at once
+    #[clap(long, conflicts_with = "payments-csv")]
+    pay: Vec<Payment>,
+    /// A CSV file with an `address,amount` line per payment, with the amount in SOL
+    #[clap(long)]
+    payments_csv: Option<PathBuf>,
+}
+
+impl Recipients {
+    pub fn payments(&self) -> Result<Vec<Payment>, Error> {
+        if let Some(to) = self.to {
+            let amount = self.amount.or(self.lamports).ok_or(Error::MissingAmount)?;
+            Ok(vec![Payment { to, amount }])
+        } else if let Some(path) = &self.payments_csv {
+            batch::read_csv(path)
+        } else {
+            Ok(self.pay.clone())
+        }
+    }
+}
+
+/// What was signed: a transaction, or the raw bytes of a message.
+#[derive(Debug, Args)]
+pub struct SignedMessage {
+    /// A transaction in base58/base64/hex, its message is what was signed
+    #[clap(long, parse(try_from_str = wire::decode), conflicts_with_all = &["file", "message"])]
+    pub transaction: Option<Transaction>,
+    /// A file holding the raw transaction, as written by `aggregate-signatures --out`
+    #[clap(long, conflicts_with = "message")]
+    pub file: Option<PathBuf>,
+    /// The signed bytes, e.g. a serialized transaction message
+    #[clap(long, required_unless_present_any = &["transaction", "file"])]
+    pub message: Option<String>,
+    /// The encoding of `--message`: base58/base64/hex
+    #[clap(long, default_value = "hex")]
+    pub encoding: Encoding,
+}
+
+impl SignedMessage {
+    /// The signed bytes, along with the transaction if they're its message.
+    pub fn read(&self) -> Result<(Vec<u8>, Option<Transaction>), Error> {
+        let tx = match (&self.transaction, &self.file) {
+            (Some(tx), _) => Some(tx.clone()),
+            (None, Some(path)) => Some(wire::read_file(path)?),
+            (None, None) => None,
+        };
+        match (tx, &self.message) {
+            (Some(tx), _) => Ok((tx.message_data(), Some(tx))),
+            (None, Some(message)) => Ok((verify::decode_bytes(message, self.encoding)?, None)),
+            (None, None) => unreachable!("clap requires a transaction, a file or a message"),
+        }
+    }
+}
+
+/// Which cluster to talk to, and how patient to be with it.
+#[derive(Debug, Args)]
+pub struct Connection {
+    /// Choose the desired network: Mainnet/Testnet/Devnet [default: testnet]
+    #[clap(long)]
+    net: Option<Network>,
+    /// RPC endpoints to use instead of the network's public one, repeat it to fail over to the next one on errors
+    #[clap(long, number_of_values = 1)]
+    url: Vec<String>,
+    /// How many endpoints must agree on the balance and the recent block hash
+    #[clap(default_value = "1", long)]
+    quorum: usize,
+    /// The commitment level of queries and confirmations: processed/confirmed/finalized
+    #[clap(default_value = "finalized", long, parse(try_from_str = parse_commitment))]
+    commitment: CommitmentConfig,
+    /// How many seconds to wait for an RPC response, and for a transaction to be confirmed
+    #[clap(default_value = "60", long)]
+    timeout: u64,
+}
+
+impl Connection {
+    pub fn rpc(&self) -> Result<Rpc, Error> {
+        Rpc::new(&self.urls(), self.timeout(), self.commitment, self.quorum)
+    }
+
+    /// Whether a network or an endpoint was picked, for the commands that only go online when asked to.
+    pub fn is_given(&self) -> bool {
+        self.net.is_some() || !self.url.is_empty()
+    }
+
+    /// The websocket endpoint of the first RPC endpoint.
+    pub fn websocket_url(&self) -> String {
+        websocket_url(&self.urls()[0])
+    }
+
+    pub fn commitment(&self) -> CommitmentConfig {
+        self.commitment
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+
+    fn urls(&self) -> Vec<String> {
+        if self.url.is_empty() {
+            vec![self.net.as_ref().unwrap_or(&Network::Testnet).get_cluster_url().to_string()]
+        } else {
+            self.url.clone()
+        }
+    }
+}
+
+/// Derive the websocket endpoint the way the Solana CLI does: `ws` instead of `http`, on the next port if one is given.
+fn websocket_url(rpc_url: &str) -> String {
+    let (scheme, rest) = match rpc_url.split_once("://") {
+        Some(("https", rest)) => ("wss", rest),
+        Some((_, rest)) => ("ws", rest),
+        None => ("ws", rpc_url),
+    };
+    let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    match authority.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && !port.contains(']') => match port.parse::<u16>() {
+            Ok(port) => format!("{}://{}:{}{}", scheme, host, port.saturating_add(1), path),
+            Err(_) => format!("{}://{}{}", scheme, authority, path),
+        },
+        _ => format!("{}://{}{}", scheme, authority, path),
+    }
+}
+
 #[derive(Debug)]
 pub enum Network {
     Mainnet,
@@ -162,7 +738,97 @@ impl FromStr for Network {
     }
 }
 
+/// Only the current commitment levels, not the deprecated aliases `CommitmentLevel` still accepts.
+fn parse_commitment(s: &str) -> Result<CommitmentConfig, Error> {
+    match s {
+        "processed" => Ok(CommitmentConfig::processed()),
+        "confirmed" => Ok(CommitmentConfig::confirmed()),
+        "finalized" => Ok(CommitmentConfig::finalized()),
+        _ => Err(Error::WrongCommitment(s.to_string())),
+    }
+}
+
 fn parse_keypair_bs58(s: &str) -> Result<Keypair, Error> {
     let decoded = bs58::decode(s).into_vec()?;
     Ok(Keypair::from_bytes(&decoded)?)
 }
+
+/// Parse a decimal SOL amount into lamports without going through floating point.
+pub fn parse_sol(s: &str) -> Result<u64, Error> {
+    let invalid = || Error::InvalidAmount(s.to_string());
+    let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+    let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if (whole.is_empty() && fraction.is_empty()) || !is_digits(whole) || !is_digits(fraction) {
+        return Err(invalid());
+    }
+    if fraction.len() > SOL_DECIMALS {
+        return Err(Error::ExcessPrecision(s.to_string()));
+    }
+    let whole: u64 = if whole.is_empty() { 0 } else { whole.parse().map_err(|_| invalid())? };
+    let fraction: u64 = format!("{:0<width$}", fraction, width = SOL_DECIMALS).parse().map_err(|_| invalid())?;
+    whole.checked_mul(LAMPORTS_PER_SOL).and_then(|l| l.checked_add(fraction)).ok_or_else(invalid)
+}
+
+/// Format lamports as an exact decimal SOL amount.
+pub fn format_sol(lamports: u64) -> String {
+    let fraction = format!("{:09}", lamports % LAMPORTS_PER_SOL);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        format!("{}", lamports / LAMPORTS_PER_SOL)
+    } else {
+        format!("{}.{}", lamports / LAMPORTS_PER_SOL, fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::commitment_config::CommitmentConfig;
+
+    use crate::cli::{format_sol, parse_commitment, parse_sol, websocket_url};
+    use crate::Error;
+
+    #[test]
+    fn test_parse_sol() {
+        assert_eq!(parse_sol("1").unwrap(), 1_000_000_000);
+        assert_eq!(parse_sol("0.1").unwrap(), 100_000_000);
+        assert_eq!(parse_sol(".5").unwrap(), 500_000_000);
+        assert_eq!(parse_sol("2.").unwrap(), 2_000_000_000);
+        assert_eq!(parse_sol("0.000000001").unwrap(), 1);
+        assert_eq!(parse_sol("1.000000000").unwrap(), 1_000_000_000);
+        assert_eq!(parse_sol("18446744073.709551615").unwrap(), u64::MAX);
+        // 0.1 + 0.2 isn't 0.3 in floating point.
+        assert_eq!(parse_sol("0.3").unwrap(), parse_sol("0.1").unwrap() + parse_sol("0.2").unwrap());
+
+        assert!(matches!(parse_sol("0.0000000001"), Err(Error::ExcessPrecision(_))));
+        for invalid in ["", ".", "-1", "+1", "1e9", "1.2.3", " 1", "0x10", "18446744073.709551616", "NaN"] {
+            assert!(matches!(parse_sol(invalid), Err(Error::InvalidAmount(_))), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_format_sol() {
+        for lamports in [0, 1, 10, 100_000_000, 1_000_000_000, 1_500_000_001, u64::MAX] {
+            assert_eq!(parse_sol(&format_sol(lamports)).unwrap(), lamports);
+        }
+        assert_eq!(format_sol(1_500_000_000), "1.5");
+        assert_eq!(format_sol(2_000_000_000), "2");
+    }
+
+    #[test]
+    fn test_parse_commitment() {
+        assert_eq!(parse_commitment("processed").unwrap(), CommitmentConfig::processed());
+        assert_eq!(parse_commitment("confirmed").unwrap(), CommitmentConfig::confirmed());
+        assert_eq!(parse_commitment("finalized").unwrap(), CommitmentConfig::finalized());
+        for invalid in ["max", "recent", "Finalized", ""] {
+            assert!(matches!(parse_commitment(invalid), Err(Error::WrongCommitment(_))), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_websocket_url() {
+        assert_eq!(websocket_url("https://api.testnet.solana.com"), "wss://api.testnet.solana.com");
+        assert_eq!(websocket_url("http://127.0.0.1:8899"), "ws://127.0.0.1:8900");
+        assert_eq!(websocket_url("https://rpc.example.com:443/token/abc"), "wss://rpc.example.com:444/token/abc");
+        assert_eq!(websocket_url("http://[::1]"), "ws://[::1]");
+    }
+}
@@ -0,0 +1,404 @@
+//! A long running signer holding one party's share, answering signing requests that pass its policy.
+//!
+//! It answers one JSON line per connection on a local TCP socket, and closes it afterwards. Every request carries the
+//! `token`:
+//! - `{"method": "pubkey"}` returns `{"pubkey": ...}`
+//! - `{"method": "step_one"}` returns `{"session": ..., "message": ...}`, the secret state never leaves the cosigner
+//! - `{"method": "step_two", "session": ..., "params": {...}, "first_messages": [...]}` returns
+//!   `{"partial_signature": ...}`, where `params` has the `keys`, the `payments` (with lamport amounts), the `memo`,
+//!   the `recent_block_hash`, and optionally the `label` of a derived address and a separate `fee_payer`
+//!
+//! Failures return `{"error": ...}`. Both steps are recorded in the audit log. Only key sets verified with `setup` on
+//! this machine are signed for.
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use ring::constant_time::verify_slices_are_equal;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use zeroize::Zeroizing;
+
+use crate::audit::{self, Action, AuditLog, Event};
+use crate::batch::Payment;
+use crate::policy::Policy;
+use crate::secret::Secret;
+use crate::serialization::{Encoding, SecretAggStepOne, Serialize};
+use crate::tss::{self, SessionParams};
+use crate::wallet::WalletStore;
+use crate::Error;
+
+/// Sessions that never got to step two are forgotten after this long.
+const SESSION_TTL: Duration = Duration::from_secs(10 * 60);
+const MAX_PENDING_SESSIONS: usize = 64;
+/// How long a client has to send its whole request, so no one can hold the socket.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+
+pub struct Cosigner {
+    keypair: Secret<Keypair>,
+    token: Zeroizing<String>,
+    policy: Policy,
+    audit: AuditLog,
+    wallets: WalletStore,
+    pending: HashMap<String, (Instant, SecretAggStepOne)>,
+}
+
+impl Cosigner {
+    pub fn new(
+        keypair: Secret<Keypair>,
+        token: Zeroizing<String>,
+        policy: Policy,
+        audit: AuditLog,
+        wallets: WalletStore,
+    ) -> Self {
+        Self { keypair, token, policy, audit, wallets, pending: HashMap::new() }
+    }
+
+    /// Serve connections one after the other, forever.
+    pub fn serve(&mut self, listener: TcpListener) -> Result<(), Error> {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| self.serve_connection(stream));
+            if let Err(e) = result {
+                eprintln!("Connection failed: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Answer the connection's first line and close it, whether it was authenticated or not.
+    fn serve_connection(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        let request = read_request(&mut stream)?;
+        writeln!(stream, "{}", self.handle(&request))
+    }
+
+    /// Answer a single JSON request.
+    pub fn handle(&mut self, request: &str) -> Value {
+        self.try_handle(request).unwrap_or_else(|e| {
+            eprintln!("Refused a request: {}", e);
+            json!({ "error": e.to_string() })
+        })
+    }
+
+    fn try_handle(&mut self, request: &str) -> Result<Value, Error> {
+        let request: Value = serde_json::from_str(request).map_err(|e| Error::InvalidRequest(e.to_string()))?;
+        // An empty token would let in any request that doesn't carry one.
+        let token = request["token"].as_str().filter(|token| !token.is_empty()).ok_or(Error::Unauthorized)?;
+        if self.token.is_empty() || verify_slices_are_equal(token.as_bytes(), self.token.as_bytes()).is_err() {
+            return Err(Error::Unauthorized);
+        }
+        match request["method"].as_str() {
+            Some("pubkey") => Ok(json!({ "pubkey": self.keypair.pubkey().to_string() })),
+            Some("step_one") => self.step_one(),
+            Some("step_two") => self.step_two(&request),
+            _ => Err(Error::InvalidRequest("unknown method".to_string())),
+        }
+    }
+
+    fn step_one(&mut self) -> Result<Value, Error> {
+        self.pending.retain(|_, (created, _)| created.elapsed() < SESSION_TTL);
+        if self.pending.len() >= MAX_PENDING_SESSIONS {
+            return Err(Error::InvalidRequest("too many pending sessions".to_string()));
+        }
+        let (message, secret) = tss::step_one(self.clone_keypair());
+        // The same session ID the CLI logs, so entries of the cosigner and of the other parties can be matched.
+        let id = audit::nonce_session(&message);
+        self.audit.append(&Event::new(Action::StepOne, id.as_str(), vec![self.keypair.pubkey()]))?;
+        self.pending.insert(id.clone(), (Instant::now(), secret));
+        Ok(json!({ "session": id, "message": message.encode(Encoding::Base58) }))
+    }
+
+    fn step_two(&mut self, request: &Value) -> Result<Value, Error> {
+        let id = request["session"].as_str().ok_or_else(|| Error::InvalidRequest("missing session".to_string()))?;
+        let session = session_from_json(&request["params"])?;
+        let first_messages = request["first_messages"]
+            .as_array()
+            .ok_or_else(|| Error::InvalidRequest("missing first_messages".to_string()))?
+            .iter()
+            .map(|message| {
+                let message = message.as_str().ok_or_else(|| Error::InvalidRequest("invalid first_messages".into()))?;
+                Serialize::decode(message)
+                    .map_err(|error| Error::DeserializationFailed { error, field_name: "first_messages" })
+            })
+            .collect::<Result<_, _>>()?;
+        // Refusing before taking the nonces lets the session be retried with other parameters.
+        self.wallets.require_verified(&session.keys)?;
+        let tx = tss::session_transaction(&session)?;
+        self.policy.check(&session, &tx)?;
+        // The nonces are used at most once, even if signing fails.
+        let (_, secret) = self.pending.remove(id).ok_or(Error::UnknownSession)?;
+        let keypair = self.clone_keypair();
+        let signature = tss::step_two(keypair, &session, first_messages, secret, &mut self.policy)?;
+        let event = Event::new(Action::PartialSignature, id, session.keys.clone()).with_message(&tx.message);
+        self.audit.append(&event)?;
+        eprintln!("Signed {} payment(s) with block hash {}", session.payments.len(), session.recent_block_hash);
+        Ok(json!({ "partial_signature": signature.encode(Encoding::Base58) }))
+    }
+
+    fn clone_keypair(&self) -> Keypair {
+        let bytes = Zeroizing::new(self.keypair.to_bytes());
+        Keypair::from_bytes(&*bytes).expect("the bytes come from a valid keypair")
+    }
+}
+
+fn session_from_json(value: &Value) -> Result<SessionParams, Error> {
+    let invalid = |field: &str| Error::InvalidRequest(format!("missing or invalid params.{}", field));
+    let pubkey = |value: &Value, field: &str| -> Result<Pubkey, Error> {
+        value.as_str().and_then(|s| s.parse().ok()).ok_or_else(|| invalid(field))
+    };
+    let keys = value["keys"]
+        .as_array()
+        .ok_or_else(|| invalid("keys"))?
+        .iter()
+        .map(|key| pubkey(key, "keys"))
+        .collect::<Result<_, _>>()?;
+    let payments = value["payments"]
+        .as_array()
+        .ok_or_else(|| invalid("payments"))?
+        .iter()
+        .map(|payment| {
+            let amount = payment["amount"].as_u64().ok_or_else(|| invalid("payments"))?;
+            Ok(Payment { to: pubkey(&payment["to"], "payments")?, amount })
+        })
+        .collect::<Result<_, Error>>()?;
+    let memo = match &value["memo"] {
+        Value::Null => None,
+        memo => Some(memo.as_str().ok_or_else(|| invalid("memo"))?.to_string()),
+    };
+    let recent_block_hash =
+        value["recent_block_hash"].as_str().and_then(|s| s.parse().ok()).ok_or_else(|| invalid("recent_block_hash"))?;
+    let label = match &value["label"] {
+        Value::Null => None,
+        label => Some(label.as_str().ok_or_else(|| invalid("label"))?.to_string()),
+    };
+    let fee_payer = match &value["fee_payer"] {
+        Value::Null => None,
+        fee_payer => Some(pubkey(fee_payer, "fee_payer")?),
+    };
+    Ok(SessionParams { keys, payments, memo, recent_block_hash, label, fee_payer })
+}
+
+/// Read the API token from `path`, or create a random one there that only the owner can read.
+/// The token is written to a temporary file first and renamed into place, so a crash never leaves an empty token.
+pub fn load_or_create_token(path: &Path) -> Result<Zeroizing<String>, Error> {
+    let failed = |e| Error::FileFailed(path.to_path_buf(), e);
+    if path.exists() {
+        let token = Zeroizing::new(fs::read_to_string(path).map_err(failed)?);
+        if token.trim().is_empty() {
+            return Err(Error::EmptyToken(path.to_path_buf()));
+        }
+        return Ok(Zeroizing::new(token.trim().to_string()));
+    }
+    let mut token = Zeroizing::new([0u8; 32]);
+    SystemRandom::new().fill(&mut *token).map_err(|_| Error::RandomnessFailed)?;
+    let token = Zeroizing::new(hex::encode(&*token));
+
+    let file_name = path.file_name().map_or_else(Default::default, |name| name.to_string_lossy());
+    let tmp = path.with_file_name(format!(".{}.tmp", file_name));
+    let tmp_failed = |e| Error::FileFailed(tmp.clone(), e);
+    // Left over by a crash, `create_new` makes sure nobody else can already have it open.
+    let _ = fs::remove_file(&tmp);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&tmp)
+        .and_then(|mut file| {
+            file.write_all(token.as_bytes())?;
+            file.sync_all()
+        })
+        .map_err(tmp_failed)?;
+    fs::rename(&tmp, path).map_err(failed)?;
+    Ok(token)
+}
+
+/// Read a single line within `READ_TIMEOUT` in total, however slowly it trickles in.
+fn read_request(stream: &mut TcpStream) -> io::Result<Zeroizing<String>> {
+    let deadline = Instant::now() + READ_TIMEOUT;
+    let mut request = Zeroizing::new(Vec::new());
+    let mut buf = Zeroizing::new([0u8; 1024]);
+    while !request.contains(&b'\n') {
+        let time_left = deadline.saturating_duration_since(Instant::now());
+        if time_left == Duration::ZERO {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        stream.set_read_timeout(Some(time_left))?;
+        let read = stream.read(&mut buf[..])?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..read]);
+        if request.len() > MAX_REQUEST_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "request too long"));
+        }
+    }
+    let line = request.split(|&b| b == b'\n').next().unwrap_or_default();
+    let line = std::str::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(Zeroizing::new(line.trim_end_matches('\r').to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::path::Path;
+    use std::thread;
+
+    use serde_json::{json, Value};
+    use solana_sdk::hash::Hash;
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::signature::{Keypair, Signer};
+    use zeroize::Zeroizing;
+
+    use crate::audit::AuditLog;
+    use crate::batch::Payment;
+    use crate::cosigner::{load_or_create_token, Cosigner};
+    use crate::policy::Policy;
+    use crate::secret::Secret;
+    use crate::serialization::{AggMessage1, PartialSignature, Serialize};
+    use crate::tss::{self, SessionParams};
+    use crate::wallet::{self, WalletStore};
+    use crate::Error;
+
+    const TOKEN: &str = "secret token";
+
+    fn cosigner(keypair: &Keypair, policy: Value, dir: &Path) -> Cosigner {
+        let keypair = Secret::new(Keypair::from_bytes(&keypair.to_bytes()).unwrap());
+        let policy = Policy::from_json(&policy).unwrap();
+        let (audit, wallets) = (AuditLog::new(dir.join("audit.log")), WalletStore::new(dir.join("wallets")));
+        Cosigner::new(keypair, Zeroizing::new(TOKEN.to_string()), policy, audit, wallets)
+    }
+
+    fn params(session: &SessionParams) -> Value {
+        json!({
+            "keys": session.keys.iter().map(Pubkey::to_string).collect::<Vec<_>>(),
+            "payments": session.payments.iter().map(|p| json!({"to": p.to.to_string(), "amount": p.amount})).collect::<Vec<_>>(),
+            "memo": session.memo,
+            "recent_block_hash": session.recent_block_hash.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_cosigner_signs_within_policy() {
+        let (ours, theirs) = (Keypair::new(), Keypair::new());
+        let dir = tempfile::tempdir().unwrap();
+        let mut cosigner = cosigner(&ours, json!({ "max_amount": "1" }), dir.path());
+        let session = SessionParams {
+            keys: vec![ours.pubkey(), theirs.pubkey()],
+            payments: vec![Payment { to: Pubkey::new_unique(), amount: 1000 }],
+            memo: Some("cosigned".to_string()),
+            recent_block_hash: Hash::new_unique(),
+            label: None,
+            fee_payer: None,
+        };
+
+        assert!(cosigner.handle(r#"{"method": "step_one"}"#)["error"].is_string());
+        assert!(cosigner.handle(&json!({"token": "wrong", "method": "step_one"}).to_string())["error"].is_string());
+        let pubkey = cosigner.handle(&json!({"token": TOKEN, "method": "pubkey"}).to_string());
+        assert_eq!(pubkey["pubkey"], ours.pubkey().to_string());
+
+        let response = cosigner.handle(&json!({"token": TOKEN, "method": "step_one"}).to_string());
+        let id = response["session"].as_str().unwrap();
+        let cosigner_msg: AggMessage1 = Serialize::decode(response["message"].as_str().unwrap()).unwrap();
+        let (their_msg, their_secret) = tss::step_one(Keypair::from_bytes(&theirs.to_bytes()).unwrap());
+
+        let step_two = |params: Value, id: &str| {
+            json!({
+                "token": TOKEN,
+                "method": "step_two",
+                "session": id,
+                "params": params,
+                "first_messages": [their_msg.serialize_bs58()],
+            })
+            .to_string()
+        };
+        // Key sets that weren't verified are refused, the nonces survive the refusal.
+        assert!(cosigner.handle(&step_two(params(&session), id))["error"].as_str().unwrap().contains("setup"));
+        let proofs = [wallet::prove(&ours, &session.keys).unwrap(), wallet::prove(&theirs, &session.keys).unwrap()];
+        WalletStore::new(dir.path().join("wallets")).save(&wallet::verify(&session.keys, &proofs).unwrap()).unwrap();
+
+        // So are payments over the limit.
+        let mut greedy = session.clone();
+        greedy.payments[0].amount = 2_000_000_000;
+        assert!(cosigner.handle(&step_two(params(&greedy), id))["error"].as_str().unwrap().contains("policy"));
+
+        let response = cosigner.handle(&step_two(params(&session), id));
+        let cosigner_sig: PartialSignature =
+            Serialize::decode(response["partial_signature"].as_str().unwrap()).unwrap();
+        let their_sig =
+            tss::step_two(theirs, &session, vec![cosigner_msg], their_secret, &mut Policy::default()).unwrap();
+        tss::sign_and_broadcast(&session, vec![cosigner_sig, their_sig]).unwrap();
+
+        // A session can only be used once.
+        assert!(cosigner.handle(&step_two(params(&session), id))["error"].is_string());
+
+        // Only what was actually done is in the audit log: step one and the signature, not the refusals.
+        let log = AuditLog::new(dir.path().join("audit.log"));
+        assert_eq!(log.verify().unwrap(), 2);
+        let entries = log.entries().unwrap();
+        assert_eq!(entries[0]["action"], "step_one");
+        assert_eq!(entries[1]["action"], "partial_signature");
+        assert_eq!(entries[1]["session"], id);
+        assert_eq!(entries[1]["amount"], 1000);
+    }
+
+    #[test]
+    fn test_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        let token = load_or_create_token(&path).unwrap();
+        assert_eq!(token.len(), 64);
+        assert_eq!(load_or_create_token(&path).unwrap(), token);
+
+        // A truncated token file must not open the API to requests without a token.
+        std::fs::write(&path, "\n").unwrap();
+        assert!(matches!(load_or_create_token(&path), Err(Error::EmptyToken(_))));
+        let keypair = Secret::new(Keypair::new());
+        let (audit, wallets) =
+            (AuditLog::new(dir.path().join("audit.log")), WalletStore::new(dir.path().join("wallets")));
+        let mut cosigner = Cosigner::new(keypair, Zeroizing::new(String::new()), Policy::default(), audit, wallets);
+        assert!(cosigner.handle(r#"{"method": "pubkey"}"#)["error"].is_string());
+        assert!(cosigner.handle(r#"{"token": "", "method": "pubkey"}"#)["error"].is_string());
+    }
+
+    #[test]
+    fn test_cosigner_over_tcp() {
+        let keypair = Keypair::new();
+        let dir = tempfile::tempdir().unwrap();
+        let mut cosigner = cosigner(&keypair, json!({}), dir.path());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || cosigner.serve(listener).unwrap());
+
+        let request = |request: Value| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            writeln!(stream, "{}", request).unwrap();
+            let mut lines = BufReader::new(stream).lines();
+            let response: Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+            // The cosigner hangs up after a single answer.
+            assert!(lines.next().is_none());
+            response
+        };
+        for _ in 0..2 {
+            let response = request(json!({"token": TOKEN, "method": "pubkey"}));
+            assert_eq!(response["pubkey"], keypair.pubkey().to_string());
+        }
+        let response = request(json!({"token": "wrong", "method": "pubkey"}));
+        assert_eq!(response["error"], Error::Unauthorized.to_string());
+
+        // A client that never finishes its line is cut off.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "{{\"token\": ").unwrap();
+        let mut rest = String::new();
+        assert_eq!(stream.read_to_string(&mut rest).unwrap(), 0);
+        let response = request(json!({"token": TOKEN, "method": "pubkey"}));
+        assert_eq!(response["pubkey"], keypair.pubkey().to_string());
+    }
+}
@@ -1,43 +1,217 @@
 use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::PathBuf;
 
 use bs58::decode::Error as Bs58Error;
 use solana_client::client_error::ClientError;
+use solana_client::pubsub_client::PubsubClientError;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::TransactionError;
 
-use crate::serialization::Error as DeserializationError;
+use crate::policy::Rule;
+use crate::serialization::{Encoding, Error as DeserializationError};
 
 #[derive(Debug)]
 pub enum Error {
     WrongNetwork(String),
+    WrongEncoding(String),
+    WrongCommitment(String),
+    InvalidAmount(String),
+    MissingAmount,
+    InvalidPayment(String),
+    InvalidCsvLine(usize, Box<Error>),
+    NoPayments,
+    TransactionTooLarge,
+    BatchOutOfRange { index: usize, batches: usize },
+    FileFailed(PathBuf, io::Error),
+    ExcessPrecision(String),
     BadBase58(Bs58Error),
     WrongKeyPair(ed25519_dalek::SignatureError),
     AirdropFailed(ClientError),
+    AirdropNotCredited { signature: Option<Signature>, attempts: u32 },
     RecentHashFailed(ClientError),
     ConfirmingTransactionFailed(ClientError),
     BalaceFailed(ClientError),
     SendTransactionFailed(ClientError),
+    FeeFailed(ClientError),
+    SimulationFailed(ClientError),
+    TransactionWouldFail(TransactionError),
+    RentFailed(ClientError),
+    TokenAccountsFailed(ClientError),
+    InsufficientFunds { address: Pubkey, balance: u64, needed: u64 },
+    NotRentExempt { address: Pubkey, balance: u64, minimum: u64 },
     DeserializationFailed { error: DeserializationError, field_name: &'static str },
     MismatchMessages,
     InvalidSignature,
     KeyPairIsNotInKeys,
+    DuplicateKey(Pubkey),
+    InvalidTransaction(String),
+    TransactionFailed { signature: Signature, error: TransactionError },
+    TransactionExpired(Signature),
+    ConfirmationTimeout(Signature),
+    InvalidQuorum { quorum: usize, endpoints: usize },
+    NoQuorum { needed: usize, agreeing: usize },
+    WrongSignatureCount { keys: usize, signatures: usize },
+    NotConnected,
+    RandomnessFailed,
+    PasswordFailed(io::Error),
+    WrongPassword,
+    InvalidKeystore(String),
+    InvalidPolicy(String),
+    PolicyViolation { rule: Rule, reason: String },
+    InvalidRequest(String),
+    Unauthorized,
+    EmptyToken(PathBuf),
+    UnknownSession,
+    CosignerFailed(io::Error),
+    UnsupportedEncoding(Encoding),
+    PromptFailed(io::Error),
+    SigningDeclined,
+    NoHomeDirectory,
+    AuditTampered { line: usize, reason: String },
+    WrongExportFormat(String),
+    SubscriptionFailed(PubsubClientError),
+    SubscriptionClosed,
+    NotASigner(Pubkey),
+    MissingSigner(Pubkey),
+    InvalidLoaderOperation(String),
+    InvalidMessage(String),
+    InvalidPartialSignature { signer: Pubkey, reason: String },
+    InvalidProof(String),
+    InvalidDescriptor(String),
+    UnverifiedWallet(Pubkey),
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::WrongNetwork(net) => write!(f, "Unrecognized network: {}, please select Mainnet/Testnet/Devnet", net),
+            Self::WrongCommitment(commitment) => {
+                write!(f, "Unrecognized commitment: {}, please select processed/confirmed/finalized", commitment)
+            }
+            Self::InvalidAmount(amount) => write!(f, "Invalid amount: {}, expected a decimal number of SOL", amount),
+            Self::MissingAmount => write!(f, "Please pass either --amount or --lamports"),
+            Self::InvalidPayment(payment) => {
+                write!(f, "Invalid payment: {}, expected <ADDRESS>=<AMOUNT> with the amount in SOL", payment)
+            }
+            Self::InvalidCsvLine(line, e) => write!(f, "Invalid payment in line {}: {}", line, e),
+            Self::NoPayments => write!(f, "There are no payments to make"),
+            Self::TransactionTooLarge => write!(f, "A single payment doesn't fit into a transaction"),
+            Self::BatchOutOfRange { index, batches } => {
+                write!(f, "There is no transaction {}, the payments fit into {} transaction(s)", index, batches)
+            }
+            Self::FileFailed(path, e) => write!(f, "Failed reading {}: {}", path.display(), e),
+            Self::ExcessPrecision(amount) => {
+                write!(f, "Invalid amount: {}, SOL has at most 9 decimal places", amount)
+            }
+            Self::WrongEncoding(enc) => {
+                write!(f, "Unrecognized encoding: {}, please select base58/base64/hex/json", enc)
+            }
             Self::BadBase58(e) => write!(f, "Based58 Error: {}", e),
             Self::WrongKeyPair(e) => write!(f, "Failed deserializing keypair: {}", e),
             Self::AirdropFailed(e) => write!(f, "Failed asking for an airdrop: {}", e),
+            Self::AirdropNotCredited { signature: Some(sig), attempts } => {
+                write!(f, "The airdrop wasn't credited after {} attempts, last transaction ID: {}", attempts, sig)
+            }
+            Self::AirdropNotCredited { signature: None, attempts } => {
+                write!(f, "The faucet didn't accept the airdrop after {} attempts", attempts)
+            }
             Self::RecentHashFailed(e) => write!(f, "Failed recieving the latest hash: {}", e),
             Self::ConfirmingTransactionFailed(e) => write!(f, "Failed confirming transaction: {}", e),
             Self::BalaceFailed(e) => write!(f, "Failed checking balance: {}", e),
             Self::SendTransactionFailed(e) => write!(f, "Failed sending transaction: {}", e),
+            Self::FeeFailed(e) => write!(f, "Failed checking the transaction fee: {}", e),
+            Self::SimulationFailed(e) => write!(f, "Failed simulating the transaction: {}", e),
+            Self::TransactionWouldFail(e) => write!(f, "The transaction would fail: {}", e),
+            Self::RentFailed(e) => write!(f, "Failed checking the rent exemption minimum: {}", e),
+            Self::TokenAccountsFailed(e) => write!(f, "Failed fetching the token accounts: {}", e),
+            Self::InsufficientFunds { address, balance, needed } => {
+                write!(
+                    f,
+                    "Insufficient funds in {}, the balance is {} lamports but {} are needed",
+                    address, balance, needed
+                )
+            }
+            Self::NotRentExempt { address, balance, minimum } => write!(
+                f,
+                "{} would be left with {} lamports, below the rent exemption minimum of {}",
+                address, balance, minimum
+            ),
             Self::DeserializationFailed { error, field_name } => {
                 write!(f, "Failed deserializing {}: {}", field_name, error)
             }
             Self::MismatchMessages => write!(f, "There is a mismatch between first_messages and second_messages"),
             Self::InvalidSignature => write!(f, "The resulting signature doesn't match the transaction"),
             Self::KeyPairIsNotInKeys => write!(f, "The provided keypair is not in the list of pubkeys"),
+            Self::DuplicateKey(key) => write!(f, "The key {} is listed more than once", key),
+            Self::InvalidTransaction(e) => write!(f, "Invalid transaction: {}", e),
+            Self::TransactionFailed { signature, error } => {
+                write!(f, "Transaction {} landed but failed: {}", signature, error)
+            }
+            Self::InvalidQuorum { quorum, endpoints } => {
+                write!(f, "A quorum of {} can't be reached with {} endpoint(s)", quorum, endpoints)
+            }
+            Self::NoQuorum { needed, agreeing } => {
+                write!(f, "Only {} endpoint(s) agreed, {} are needed", agreeing, needed)
+            }
+            Self::WrongSignatureCount { keys, signatures } => {
+                write!(f, "Got {} partial signature(s), one is needed from each of the {} keys", signatures, keys)
+            }
+            Self::NotConnected => write!(f, "This command talks to the cluster, but no connection was set up for it"),
+            Self::RandomnessFailed => write!(f, "The system's random number generator failed"),
+            Self::PasswordFailed(e) => write!(f, "Failed reading the password: {}", e),
+            Self::WrongPassword => write!(f, "Wrong password, or the keystore was tampered with"),
+            Self::InvalidKeystore(e) => write!(f, "Invalid keystore: {}", e),
+            Self::InvalidPolicy(e) => write!(f, "Invalid policy: {}", e),
+            Self::PolicyViolation { rule, reason } => {
+                write!(f, "Refused by the {} rule of the signing policy: {}", rule, reason)
+            }
+            Self::InvalidRequest(e) => write!(f, "Invalid request: {}", e),
+            Self::Unauthorized => write!(f, "Missing or wrong token"),
+            Self::EmptyToken(path) => {
+                write!(f, "The API token in {} is empty, delete the file to create a new one", path.display())
+            }
+            Self::CosignerFailed(e) => write!(f, "The cosigner failed: {}", e),
+            Self::UnknownSession => write!(f, "Unknown or expired signing session, start again from step one"),
+            Self::ConfirmationTimeout(signature) => write!(
+                f,
+                "Timed out waiting for transaction {}, it can still land until its recent block hash expires",
+                signature
+            ),
+            Self::TransactionExpired(signature) => write!(
+                f,
+                "Transaction {} expired without landing, it's safe to sign it again with a new recent block hash",
+                signature
+            ),
+            Self::UnsupportedEncoding(enc) => {
+                write!(f, "Transactions can't be encoded as {:?}, please select base58/base64/hex", enc)
+            }
+            Self::PromptFailed(e) => write!(f, "Failed reading the answer: {}", e),
+            Self::SigningDeclined => write!(f, "Signing declined, nothing was signed"),
+            Self::NoHomeDirectory => write!(f, "Couldn't find a home directory, please set SOLANA_TSS_HOME"),
+            Self::AuditTampered { line, reason } => {
+                write!(f, "The audit log was tampered with at line {}: {}", line, reason)
+            }
+            Self::SubscriptionFailed(e) => write!(f, "Failed subscribing to updates: {}", e),
+            Self::SubscriptionClosed => write!(f, "The websocket connection closed"),
+            Self::NotASigner(key) => write!(f, "{} doesn't sign this transaction", key),
+            Self::MissingSigner(key) => write!(f, "The transaction is missing the signature of {}", key),
+            Self::InvalidLoaderOperation(e) => write!(f, "Invalid loader operation: {}", e),
+            Self::InvalidMessage(e) => write!(f, "Invalid message: {}", e),
+            Self::InvalidPartialSignature { signer, reason } => {
+                write!(f, "The partial signature of {} is invalid: {}", signer, reason)
+            }
+            Self::InvalidProof(e) => write!(f, "Invalid proof of possession: {}", e),
+            Self::InvalidDescriptor(e) => write!(f, "Invalid wallet descriptor: {}", e),
+            Self::UnverifiedWallet(aggregate) => write!(
+                f,
+                "The key set of {} wasn't verified, every party has to prove it holds its share with `setup` first",
+                aggregate
+            ),
+            Self::WrongExportFormat(format) => {
+                write!(f, "Unrecognized export format: {}, please select json/csv", format)
+            }
         }
     }
 }
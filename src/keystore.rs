@@ -0,0 +1,125 @@
+//! Secret shares encrypted at rest with a password: PBKDF2-HMAC-SHA256 derives a ChaCha20-Poly1305 key.
+//! The public key is authenticated as associated data, so a keystore can't be swapped for another share's.
+
+use std::convert::{TryFrom, TryInto};
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::{env, fs};
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use zeroize::Zeroizing;
+
+use crate::secret::Secret;
+use crate::Error;
+
+pub const DEFAULT_ITERATIONS: u32 = 600_000;
+const PASSWORD_VAR: &str = "SOLANA_TSS_PASSWORD";
+const SALT_LEN: usize = 16;
+const VERSION: u64 = 1;
+
+/// Encrypt `keypair` with `password` into the JSON keystore format.
+pub fn encrypt(keypair: &Keypair, password: &str, iterations: u32) -> Result<String, Error> {
+    let rng = SystemRandom::new();
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill(&mut salt).and_then(|()| rng.fill(&mut nonce)).map_err(|_| Error::RandomnessFailed)?;
+    let iterations = NonZeroU32::new(iterations).ok_or_else(|| Error::InvalidKeystore("zero iterations".into()))?;
+
+    let key = derive_key(password, &salt, iterations);
+    let pubkey = keypair.pubkey();
+    let mut in_out = keypair.to_bytes().to_vec();
+    key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(pubkey.as_ref()), &mut in_out)
+        .map_err(|_| Error::InvalidKeystore("encryption failed".into()))?;
+
+    let keystore = json!({
+        "version": VERSION,
+        "pubkey": pubkey.to_string(),
+        "kdf": "pbkdf2-hmac-sha256",
+        "iterations": iterations.get(),
+        "salt": hex::encode(salt),
+        "cipher": "chacha20-poly1305",
+        "nonce": hex::encode(nonce),
+        "ciphertext": hex::encode(in_out),
+    });
+    Ok(serde_json::to_string_pretty(&keystore).expect("serializing a JSON value can't fail"))
+}
+
+/// Decrypt a keystore, failing with `WrongPassword` if it doesn't authenticate.
+pub fn decrypt(keystore: &str, password: &str) -> Result<Secret<Keypair>, Error> {
+    let invalid = |field: &str| Error::InvalidKeystore(format!("missing or invalid {}", field));
+    let value: Value = serde_json::from_str(keystore).map_err(|e| Error::InvalidKeystore(e.to_string()))?;
+    if value["version"].as_u64() != Some(VERSION) {
+        return Err(invalid("version"));
+    }
+    let hex_field = |field: &str| value[field].as_str().and_then(|s| hex::decode(s).ok()).ok_or_else(|| invalid(field));
+    let pubkey: Pubkey = value["pubkey"].as_str().and_then(|s| s.parse().ok()).ok_or_else(|| invalid("pubkey"))?;
+    let iterations = value["iterations"]
+        .as_u64()
+        .and_then(|i| u32::try_from(i).ok())
+        .and_then(NonZeroU32::new)
+        .ok_or_else(|| invalid("iterations"))?;
+    let salt = hex_field("salt")?;
+    let nonce: [u8; NONCE_LEN] = hex_field("nonce")?.try_into().map_err(|_| invalid("nonce"))?;
+    let mut in_out = Zeroizing::new(hex_field("ciphertext")?);
+
+    let key = derive_key(password, &salt, iterations);
+    let plaintext = key
+        .open_in_place(Nonce::assume_unique_for_key(nonce), Aad::from(pubkey.as_ref()), &mut in_out)
+        .map_err(|_| Error::WrongPassword)?;
+    let keypair = Keypair::from_bytes(plaintext)?;
+    if keypair.pubkey() != pubkey {
+        return Err(invalid("pubkey"));
+    }
+    Ok(Secret::new(keypair))
+}
+
+pub fn read(path: &Path, password: &str) -> Result<Secret<Keypair>, Error> {
+    decrypt(&fs::read_to_string(path).map_err(|e| Error::FileFailed(path.to_path_buf(), e))?, password)
+}
+
+/// Take the password from `SOLANA_TSS_PASSWORD`, or prompt for it on the terminal without echoing it.
+pub fn password() -> Result<Zeroizing<String>, Error> {
+    if let Ok(password) = env::var(PASSWORD_VAR) {
+        return Ok(Zeroizing::new(password));
+    }
+    rpassword::prompt_password_stderr("Keystore password: ").map(Zeroizing::new).map_err(Error::PasswordFailed)
+}
+
+fn derive_key(password: &str, salt: &[u8], iterations: NonZeroU32) -> LessSafeKey {
+    let mut key = Zeroizing::new([0u8; 32]);
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, salt, password.as_bytes(), &mut *key);
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, &*key).expect("the key has the right length"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use solana_sdk::signature::{Keypair, Signer};
+
+    use crate::keystore::{decrypt, encrypt};
+    use crate::Error;
+
+    #[test]
+    fn test_keystore_roundtrip() {
+        let keypair = Keypair::new();
+        let keystore = encrypt(&keypair, "hunter2", 1000).unwrap();
+        assert!(!keystore.contains(&keypair.to_base58_string()));
+        assert_eq!(decrypt(&keystore, "hunter2").unwrap().to_bytes(), keypair.to_bytes());
+        assert!(matches!(decrypt(&keystore, "hunter3"), Err(Error::WrongPassword)));
+
+        // Claiming the share belongs to another key breaks the authentication.
+        let mut value: Value = serde_json::from_str(&keystore).unwrap();
+        value["pubkey"] = Keypair::new().pubkey().to_string().into();
+        assert!(matches!(decrypt(&value.to_string(), "hunter2"), Err(Error::WrongPassword)));
+
+        let mut value: Value = serde_json::from_str(&keystore).unwrap();
+        value["iterations"] = 0.into();
+        assert!(matches!(decrypt(&value.to_string(), "hunter2"), Err(Error::InvalidKeystore(_))));
+        assert!(matches!(decrypt("{}", "hunter2"), Err(Error::InvalidKeystore(_))));
+    }
+}
@@ -0,0 +1,229 @@
+//! Messages for the BPF upgradeable loader, with the aggregate key as the upgrade or buffer authority.
+
+use std::fs;
+
+use solana_sdk::bpf_loader_upgradeable::{self, UpgradeableLoaderState};
+use solana_sdk::hash::Hash;
+use solana_sdk::loader_upgradeable_instruction::UpgradeableLoaderInstruction;
+use solana_sdk::message::Message;
+use solana_sdk::packet::PACKET_DATA_SIZE;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::rent::Rent;
+use solana_sdk::transaction::Transaction;
+
+use crate::cli::LoaderOperation;
+use crate::Error;
+
+/// Build the message of `operation`, authorized by `authority` and paid for by `fee_payer`, the authority by default.
+/// Every party has to build exactly the same message, so it only depends on the arguments.
+pub fn message(
+    operation: &LoaderOperation,
+    authority: &Pubkey,
+    fee_payer: Option<&Pubkey>,
+    recent_block_hash: Hash,
+) -> Result<Message, Error> {
+    let payer = fee_payer.unwrap_or(authority);
+    let invalid = |e: &str| Error::InvalidLoaderOperation(e.to_string());
+    let instructions = match operation {
+        LoaderOperation::Write { buffer, program_file, offset } => {
+            let program = fs::read(program_file).map_err(|e| Error::FileFailed(program_file.clone(), e))?;
+            let start = *offset as usize;
+            if start >= program.len() {
+                let e = format!("offset {} is past the end of the {} byte program", offset, program.len());
+                return Err(Error::InvalidLoaderOperation(e));
+            }
+            let end = program.len().min(start + write_chunk_len(buffer, authority, payer));
+            vec![bpf_loader_upgradeable::write(buffer, authority, *offset, program[start..end].to_vec())]
+        }
+        LoaderOperation::Deploy { program, buffer, max_len } => {
+            // The program account only holds the address of its data, so its rent doesn't depend on the cluster.
+            let program_len = UpgradeableLoaderState::program_len().map_err(|e| invalid(&e.to_string()))?;
+            let program_lamports = Rent::default().minimum_balance(program_len);
+            bpf_loader_upgradeable::deploy_with_max_program_len(
+                payer,
+                program,
+                buffer,
+                authority,
+                program_lamports,
+                *max_len,
+            )
+            .map_err(|e| invalid(&e.to_string()))?
+        }
+        LoaderOperation::Upgrade { program, buffer, spill } => {
+            vec![bpf_loader_upgradeable::upgrade(program, buffer, authority, spill.as_ref().unwrap_or(payer))]
+        }
+        LoaderOperation::SetAuthority { program: Some(program), new_authority, .. } => {
+            vec![bpf_loader_upgradeable::set_upgrade_authority(program, authority, new_authority.as_ref())]
+        }
+        LoaderOperation::SetAuthority { buffer: Some(buffer), new_authority: Some(new_authority), .. } => {
+            vec![bpf_loader_upgradeable::set_buffer_authority(buffer, authority, new_authority)]
+        }
+        LoaderOperation::SetAuthority { buffer: Some(_), new_authority: None, .. } => {
+            return Err(invalid("a buffer always needs an authority, only a program can be made final"))
+        }
+        LoaderOperation::SetAuthority { program: None, buffer: None, .. } => {
+            unreachable!("clap requires either a program or a buffer")
+        }
+        LoaderOperation::CloseBuffer { buffer, recipient } => {
+            vec![bpf_loader_upgradeable::close(buffer, recipient.as_ref().unwrap_or(payer), authority)]
+        }
+    };
+    let mut message = Message::new(&instructions, Some(payer));
+    message.recent_blockhash = recent_block_hash;
+    Ok(message)
+}
+
+/// Where the operation sends the lamports it frees up, if anywhere.
+pub fn recipient(operation: &LoaderOperation, payer: &Pubkey) -> Option<Pubkey> {
+    match operation {
+        LoaderOperation::Upgrade { spill, .. } => Some(spill.unwrap_or(*payer)),
+        LoaderOperation::CloseBuffer { recipient, .. } => Some(recipient.unwrap_or(*payer)),
+        _ => None,
+    }
+}
+
+/// Where the next write should start, if `message` writes into a buffer.
+pub fn next_write_offset(message: &Message) -> Option<usize> {
+    message.instructions.iter().find_map(|instruction| {
+        if message.account_keys[usize::from(instruction.program_id_index)] != bpf_loader_upgradeable::id() {
+            return None;
+        }
+        match bincode::deserialize(&instruction.data) {
+            Ok(UpgradeableLoaderInstruction::Write { offset, bytes }) => Some(offset as usize + bytes.len()),
+            _ => None,
+        }
+    })
+}
+
+/// The most program bytes a write transaction can carry.
+fn write_chunk_len(buffer: &Pubkey, authority: &Pubkey, payer: &Pubkey) -> usize {
+    let empty = bpf_loader_upgradeable::write(buffer, authority, 0, Vec::new());
+    let tx = Transaction::new_unsigned(Message::new(&[empty], Some(payer)));
+    let empty_len = bincode::serialized_size(&tx).expect("a transaction can be serialized") as usize;
+    // The length prefix of the instruction data grows from 1 to 2 bytes once the data is longer than 127 bytes.
+    PACKET_DATA_SIZE - empty_len - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::{json, Value};
+
+    use solana_sdk::bpf_loader_upgradeable;
+    use solana_sdk::hash::Hash;
+    use solana_sdk::packet::PACKET_DATA_SIZE;
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::signature::{Keypair, Signer};
+    use solana_sdk::transaction::Transaction;
+
+    use crate::cli::LoaderOperation;
+    use crate::loader::{message, next_write_offset, recipient};
+    use crate::policy::{Policy, Rule};
+    use crate::serialization::{PartialSignature, Serialize};
+    use crate::tss::{aggregate_keys, aggregate_signatures, assemble_transaction, sign_message, step_one};
+    use crate::Error;
+
+    #[test]
+    fn test_write_chunks_fit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("program.so");
+        fs::write(&path, vec![7u8; 3000]).unwrap();
+        let (authority, payer) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let write =
+            |offset| LoaderOperation::Write { buffer: Pubkey::new_unique(), program_file: path.clone(), offset };
+
+        let first = message(&write(0), &authority, Some(&payer), Hash::new_unique()).unwrap();
+        let tx_len = bincode::serialized_size(&Transaction::new_unsigned(first.clone())).unwrap() as usize;
+        assert_eq!(tx_len, PACKET_DATA_SIZE);
+        let next = next_write_offset(&first).unwrap();
+        assert!(next > 800 && next < 1232, "{}", next);
+
+        // The last chunk is shorter.
+        let last = message(&write(2500), &authority, Some(&payer), Hash::new_unique()).unwrap();
+        assert_eq!(next_write_offset(&last), Some(3000));
+        assert!(matches!(
+            message(&write(3000), &authority, None, Hash::new_unique()),
+            Err(Error::InvalidLoaderOperation(_))
+        ));
+    }
+
+    #[test]
+    fn test_policy() {
+        let (authority, allowed, other) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let close = |to| LoaderOperation::CloseBuffer { buffer: Pubkey::new_unique(), recipient: to };
+        let violated_rule = |policy: Value, operation: &LoaderOperation| {
+            let (policy, message) = (
+                Policy::from_json(&policy).unwrap(),
+                message(operation, &authority, None, Hash::new_unique()).unwrap(),
+            );
+            match policy.check_loader(&message, recipient(operation, &authority).as_ref()) {
+                Ok(()) => None,
+                Err(Error::PolicyViolation { rule, .. }) => Some(rule),
+                Err(e) => panic!("unexpected error: {}", e),
+            }
+        };
+        let loader = bpf_loader_upgradeable::id().to_string();
+
+        let recipients = json!({ "allowed_recipients": [allowed.to_string()] });
+        assert_eq!(violated_rule(recipients.clone(), &close(Some(allowed))), None);
+        assert_eq!(violated_rule(recipients.clone(), &close(Some(other))), Some(Rule::AllowedRecipients));
+        // Without a recipient the lamports go to the fee payer, the authority here.
+        assert_eq!(violated_rule(recipients, &close(None)), Some(Rule::AllowedRecipients));
+
+        let programs = json!({ "allowed_programs": [solana_sdk::system_program::id().to_string()] });
+        assert_eq!(violated_rule(programs, &close(Some(other))), Some(Rule::AllowedPrograms));
+        assert_eq!(violated_rule(json!({ "allowed_programs": [loader] }), &close(Some(other))), None);
+        let memo = json!({ "memo_pattern": ".*" });
+        assert_eq!(violated_rule(memo, &close(Some(other))), Some(Rule::MemoPattern));
+    }
+
+    #[test]
+    fn test_set_authority_with_fee_payer() {
+        let keys: Vec<_> = (0..2).map(|_| Keypair::new()).collect();
+        let pubkeys: Vec<_> = keys.iter().map(|k| k.pubkey()).collect();
+        let aggregate = aggregate_keys(pubkeys.clone(), None).unwrap().aggregate;
+        let fee_payer = Keypair::new();
+        let operation = LoaderOperation::SetAuthority {
+            program: Some(Pubkey::new_unique()),
+            buffer: None,
+            new_authority: None,
+            make_final: true,
+        };
+        let message = message(&operation, &aggregate, Some(&fee_payer.pubkey()), Hash::new_unique()).unwrap();
+        assert_eq!(message.account_keys[0], fee_payer.pubkey());
+        assert_eq!(message.account_keys[1], aggregate);
+        assert_eq!(message.instructions[0].accounts.len(), 2);
+
+        let (first_msgs, secrets): (Vec<_>, Vec<_>) =
+            keys.iter().map(|k| step_one(Keypair::from_bytes(&k.to_bytes()).unwrap())).unzip();
+        let partial_sigs: Vec<_> = keys
+            .iter()
+            .zip(secrets)
+            .enumerate()
+            .map(|(i, (key, secret))| {
+                let others = vec![Serialize::decode(&first_msgs[1 - i].serialize_bs58()).unwrap()];
+                let key = Keypair::from_bytes(&key.to_bytes()).unwrap();
+                sign_message(key, &pubkeys, None, &message, others, secret).unwrap()
+            })
+            .collect();
+        for missing in [vec![], vec![PartialSignature(partial_sigs[0].0)]] {
+            assert!(matches!(
+                aggregate_signatures(&pubkeys, None, &message, missing),
+                Err(Error::WrongSignatureCount { keys: 2, .. })
+            ));
+        }
+        let signature = aggregate_signatures(&pubkeys, None, &message, partial_sigs).unwrap();
+
+        assert!(matches!(
+            assemble_transaction(message.clone(), &aggregate, signature, &[]),
+            Err(Error::MissingSigner(key)) if key == fee_payer.pubkey()
+        ));
+        assert!(matches!(
+            assemble_transaction(message.clone(), &aggregate, signature, &[Keypair::new()]),
+            Err(Error::NotASigner(_))
+        ));
+        let tx = assemble_transaction(message, &aggregate, signature, &[fee_payer]).unwrap();
+        assert_eq!(tx.message.instructions[0].program_id(&tx.message.account_keys), &bpf_loader_upgradeable::id());
+    }
+}
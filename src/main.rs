@@ -1,110 +1,730 @@
+use std::fs;
+use std::net::TcpListener;
+use std::path::Path;
+use std::time::Duration;
+
 use clap::Parser;
-use solana_client::rpc_client::RpcClient;
+use solana_sdk::hash::Hash;
 use solana_sdk::instruction::Instruction;
 use solana_sdk::message::Message;
 use solana_sdk::signer::keypair::Keypair;
 use solana_sdk::transaction::Transaction;
-use solana_sdk::{native_token, signature::Signer, system_instruction};
+use solana_sdk::{signature::Signer, system_instruction};
 use spl_memo::solana_program::pubkey::Pubkey;
+use zeroize::Zeroizing;
 
-use crate::cli::Options;
+use crate::audit::{Action, AuditLog, Event};
+use crate::batch::Payment;
+use crate::chain::Chain;
+use crate::cli::{format_sol, AuditCommand, LoaderOperation, Options, ProgramStep, Recipients, SetupStep};
 use crate::error::Error;
-use crate::serialization::Serialize;
+use crate::policy::Policy;
+use crate::review::Review;
+use crate::serialization::{AggMessage1, Serialize};
+use crate::tss::SessionParams;
+use crate::wallet::WalletStore;
 
+mod airdrop;
+mod audit;
+mod batch;
+mod chain;
 mod cli;
+mod cosigner;
 mod error;
+mod keystore;
+mod loader;
+#[cfg(test)]
+mod mock;
+mod policy;
+mod preflight;
+mod review;
+mod rpc;
+mod secret;
+#[cfg(feature = "serde")]
+mod serde_support;
 mod serialization;
+mod simulate;
+mod submit;
 mod tss;
+mod verify;
+mod wallet;
+mod watch;
+mod wire;
 
 fn main() -> Result<(), Error> {
     let opts = Options::parse();
+    let rpc = opts.rpc()?;
+    run(opts, rpc.as_ref().map(|rpc| rpc as &dyn Chain), &audit::home()?)
+}
+
+/// Run a command, `chain` is only `None` for the commands that don't talk to the cluster.
+/// `home` holds the local state: the audit log and the verified wallets.
+fn run(opts: Options, chain: Option<&dyn Chain>, home: &Path) -> Result<(), Error> {
+    let connected = || chain.ok_or(Error::NotConnected);
     match opts {
         Options::Generate => {
             let keypair = Keypair::generate(&mut rand07::thread_rng());
             println!("secret share: {}", keypair.to_base58_string());
             println!("public share: {}", keypair.pubkey());
         }
-        Options::Balance { address, net } => {
-            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
-            let balance = rpc_client.get_balance(&address).map_err(Error::BalaceFailed)?;
+        Options::Balance { address, .. } => {
+            let balance = connected()?.balance(&address)?;
             println!("The balance of {} is: {}", address, balance);
         }
-        Options::Airdrop { to, amount, net } => {
-            // TODO: Check balance before and after, and if didn't change verify with get_signature_statuses_with_history
-            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
-            let amount = native_token::sol_to_lamports(amount);
-            let sig = rpc_client.request_airdrop(&to, amount).map_err(Error::AirdropFailed)?;
-            println!("Airdrop transaction ID: {}", sig);
-            let recent_hash = rpc_client.get_latest_blockhash().map_err(Error::RecentHashFailed)?;
-            rpc_client
-                .confirm_transaction_with_spinner(&sig, &recent_hash, rpc_client.commitment())
-                .map_err(Error::ConfirmingTransactionFailed)?;
-        }
-        Options::SendSingle { keypair, amount, to, net, memo } => {
-            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
-            let mut tx = create_unsigned_transaction(amount, &to, memo, &keypair.pubkey());
-            let recent_hash = rpc_client.get_latest_blockhash().map_err(Error::RecentHashFailed)?;
-            tx.sign(&[&keypair], recent_hash);
-            let sig = rpc_client.send_transaction(&tx).map_err(Error::SendTransactionFailed)?;
-            println!("Transaction ID: {}", sig);
-            rpc_client
-                .confirm_transaction_with_spinner(&sig, &recent_hash, rpc_client.commitment())
-                .map_err(Error::ConfirmingTransactionFailed)?;
-        }
-        Options::RecentBlockHash { net } => {
-            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
-            let recent_hash = rpc_client.get_latest_blockhash().map_err(Error::RecentHashFailed)?;
+        Options::Airdrop { to, amount, connection, no_wait } => {
+            let chain = connected()?;
+            if no_wait {
+                let sig = chain.request_airdrop(&to, amount.lamports())?;
+                println!("Airdrop transaction ID: {}", sig);
+                return Ok(());
+            }
+            let report = airdrop::verified_airdrop(chain, &to, amount.lamports(), connection.timeout())?;
+            println!("Airdrop transaction ID: {}", report.signature);
+            println!(
+                "Credited {} SOL (requested {}) to {} after {} attempt(s), the balance is now {} SOL",
+                format_sol(report.credited),
+                format_sol(report.requested),
+                to,
+                report.attempts,
+                format_sol(report.balance)
+            );
+        }
+        Options::SendSingle { keypair, amount, to, connection, memo, no_wait } => {
+            let chain = connected()?;
+            let mut tx = create_unsigned_transaction(amount.lamports(), &to, memo, &keypair.pubkey());
+            tx.sign(&[&keypair], chain.latest_blockhash()?);
+            send_and_confirm(chain, &tx, connection.timeout(), no_wait)?;
+        }
+        Options::RecentBlockHash { .. } => {
+            let recent_hash = connected()?.latest_blockhash()?;
             println!("recent block hash: {}", recent_hash);
         }
-        Options::AggregateKeys { keys } => {
-            let aggkey = tss::key_agg(keys, None)?;
-            let aggpubkey = Pubkey::new(&*aggkey.agg_public_key.to_bytes(true));
-            println!("The Aggregated Public Key: {}", aggpubkey);
+        Options::AggregateKeys { keys, label } => {
+            warn_legacy_order(&keys)?;
+            require_verified(home, &keys)?;
+            let aggkey = tss::aggregate_keys(keys, label.as_deref())?;
+            let keys: Vec<_> = aggkey.keys.iter().map(Pubkey::to_string).collect();
+            eprintln!("Canonical key order: {}", keys.join(" "));
+            println!("The Aggregated Public Key: {}", aggkey.aggregate);
         }
-        Options::AggSendStepOne { keypair } => {
+        Options::AggSendStepOne { keypair, encoding } => {
+            let party = keypair.pubkey();
             let (first_msg, secret) = tss::step_one(keypair);
+            let event = Event::new(Action::StepOne, audit::nonce_session(&first_msg), vec![party]);
+            AuditLog::in_home(home).append(&event)?;
 
-            println!("Message 1: {} (send to all other parties)", first_msg.serialize_bs58());
-            println!(
-                "Secret state: {} (keep this a secret, and pass it back to `agg-send-step-two`)",
-                secret.serialize_bs58()
-            );
+            println!("Message 1: {} (send to all other parties)", first_msg.encode(encoding));
+            let secret = Zeroizing::new(secret.encode(encoding));
+            println!("Secret state: {} (keep this a secret, and pass it back to `agg-send-step-two`)", *secret);
+        }
+        Options::PlanBatch { recipients, memo, keys, label, fee_payer } => {
+            let aggpubkey = tss::aggregate_keys(keys, label.as_deref())?.aggregate;
+            let batches = batch::plan(&recipients.payments()?, memo.as_deref(), &aggpubkey, fee_payer.as_ref())?;
+            for (i, payments) in batches.iter().enumerate() {
+                let total = payments.iter().map(|p| p.amount).sum();
+                println!(
+                    "Transaction {}: {} payment(s), {} SOL (sign with `--batch {}`)",
+                    i,
+                    payments.len(),
+                    format_sol(total),
+                    i
+                );
+                for payment in payments {
+                    println!("  {} {}", payment.to, format_sol(payment.amount));
+                }
+            }
         }
         Options::AggSendStepTwo {
             keypair,
-            amount,
-            to,
+            recipients,
+            batch,
             memo,
             recent_block_hash,
             keys,
             first_messages,
             secret_state,
+            encoding,
+            policy,
+            yes,
+            label,
+            fee_payer,
+            sign_as,
+            ..
+        } => {
+            require_verified(home, &keys)?;
+            let session = session_params(keys, &recipients, batch, memo, recent_block_hash, label, fee_payer)?;
+            let mut policy = policy.as_deref().map(Policy::read).transpose()?.unwrap_or_default();
+            let tx = tss::session_transaction(&session)?;
+            eprint!("{}", Review(&tx.message));
+            if let Some(chain) = chain {
+                let from = tss::aggregate_keys(session.keys.clone(), session.label.as_deref())?.aggregate;
+                let preflight = preflight::check(chain, &tx, &from, &session.payments)?;
+                eprintln!("Balance: {} lamports, fee: {} lamports", preflight.balance, preflight.fee);
+                let simulation = chain.simulate(&tx, false)?;
+                eprint!("{}", simulation);
+                simulation.into_result()?;
+            }
+            if !yes && !review::confirm("Sign this transaction?")? {
+                return Err(Error::SigningDeclined);
+            }
+            let nonces = AggMessage1 { sender: keypair.pubkey(), public_nonces: secret_state.public_nonces.clone() };
+            if !sign_as.is_empty() {
+                require_verified(home, &sign_as)?;
+            }
+            let participants = if sign_as.is_empty() { session.keys.clone() } else { sign_as.clone() };
+            let event = Event::new(Action::PartialSignature, audit::nonce_session(&nonces), participants);
+            let sig = if sign_as.is_empty() {
+                tss::step_two(keypair, &session, first_messages, secret_state, &mut policy)?
+            } else {
+                // Co-signing, e.g. as the fee payer, doesn't spend from the session's address, so it isn't recorded
+                // against the spending limits.
+                policy.check(&session, &tx)?;
+                tss::sign_message(keypair, &sign_as, None, &tx.message, first_messages, secret_state)?
+            };
+            AuditLog::in_home(home).append(&event.with_message(&tx.message))?;
+            println!("Partial signature: {}", sig.encode(encoding));
+        }
+        Options::AggregateSignaturesAndBroadcast {
+            signatures,
+            recipients,
+            batch,
+            memo,
+            recent_block_hash,
+            connection,
+            keys,
+            simulate_only,
+            no_wait,
+            label,
+            fee_payer,
+            signers,
+        } => {
+            require_verified(home, &keys)?;
+            let session = session_params(keys, &recipients, batch, memo, recent_block_hash, label, fee_payer)?;
+            let mut tx = tss::sign_and_broadcast(&session, signatures)?;
+            for signer in &signers {
+                tss::add_signer(&mut tx, signer)?;
+            }
+            tss::check_complete(&tx)?;
+            let audit = AuditLog::in_home(home);
+            audit.append(&transaction_event(Action::Aggregation, &tx, session.keys.clone()))?;
+            let chain = connected()?;
+            let simulation = chain.simulate(&tx, true)?;
+            eprint!("{}", simulation);
+            simulation.into_result()?;
+            if simulate_only {
+                return Ok(());
+            }
+            send_and_confirm(chain, &tx, connection.timeout(), no_wait)?;
+            audit.append(&transaction_event(Action::Broadcast, &tx, session.keys))?;
+        }
+        Options::AggregateSignatures {
+            signatures,
+            recipients,
+            batch,
+            memo,
+            recent_block_hash,
+            keys,
+            encoding,
+            out,
+            label,
+            fee_payer,
+            sign_as,
+            signers,
+            file,
+        } => {
+            require_verified(home, &keys)?;
+            let session = session_params(keys, &recipients, batch, memo, recent_block_hash, label, fee_payer)?;
+            let mut tx = tss::session_transaction(&session)?;
+            if let Some(path) = file {
+                let partial = wire::read_file(&path)?;
+                if partial.message != tx.message {
+                    let e = format!("{} isn't a transaction of this session", path.display());
+                    return Err(Error::InvalidTransaction(e));
+                }
+                tx = partial;
+            }
+            let (signing_keys, signing_label) =
+                if sign_as.is_empty() { (session.keys, session.label.as_deref()) } else { (sign_as, None) };
+            require_verified(home, &signing_keys)?;
+            tss::add_aggregate_signature(&mut tx, &signing_keys, signing_label, signatures)?;
+            for signer in &signers {
+                tss::add_signer(&mut tx, signer)?;
+            }
+            AuditLog::in_home(home).append(&transaction_event(Action::Aggregation, &tx, signing_keys))?;
+            match out {
+                Some(path) => {
+                    wire::write_file(&path, &tx)?;
+                    eprintln!("Wrote the signed transaction to {}", path.display());
+                }
+                None => println!("Signed transaction: {}", wire::encode(&tx, encoding)?),
+            }
+            report_missing_signers(&tx, recent_block_hash);
+        }
+        Options::Broadcast { transaction, file, connection, no_wait } => {
+            let tx = match (transaction, file) {
+                (Some(tx), _) => tx,
+                (None, Some(path)) => wire::read_file(&path)?,
+                (None, None) => unreachable!("clap requires either a transaction or --file"),
+            };
+            tss::check_complete(&tx)?;
+            let chain = connected()?;
+            let simulation = chain.simulate(&tx, true)?;
+            eprint!("{}", simulation);
+            simulation.into_result()?;
+            let signers = tx.message.account_keys[..usize::from(tx.message.header.num_required_signatures)].to_vec();
+            send_and_confirm(chain, &tx, connection.timeout(), no_wait)?;
+            AuditLog::in_home(home).append(&transaction_event(Action::Broadcast, &tx, signers))?;
+        }
+        Options::EncryptShare { keypair, out, iterations } => {
+            let password = keystore::password()?;
+            let encrypted = keystore::encrypt(&keypair, &password, iterations)?;
+            fs::write(&out, encrypted).map_err(|e| Error::FileFailed(out.clone(), e))?;
+            println!("Wrote the encrypted share of {} to {}", keypair.pubkey(), out.display());
+        }
+        Options::Cosigner { keystore: keystore_path, policy, listen, token_file } => {
+            let keypair = keystore::read(&keystore_path, &keystore::password()?)?;
+            let policy = Policy::read(&policy)?;
+            let token_file = token_file.unwrap_or_else(|| keystore_path.with_extension("token"));
+            let token = cosigner::load_or_create_token(&token_file)?;
+            if !listen.ip().is_loopback() {
+                eprintln!("Warning: listening on {}, which isn't a loopback address", listen);
+            }
+            let listener = TcpListener::bind(listen).map_err(Error::CosignerFailed)?;
+            eprintln!("Cosigning for {} on {}, the token is in {}", keypair.pubkey(), listen, token_file.display());
+            let wallets = WalletStore::in_home(home);
+            cosigner::Cosigner::new(keypair, token, policy, AuditLog::in_home(home), wallets).serve(listener)?;
+        }
+        Options::Audit { command } => {
+            let audit = AuditLog::in_home(home);
+            match command {
+                AuditCommand::Verify => {
+                    let entries = audit.verify()?;
+                    println!("The audit log at {} is intact, {} entries", audit.path().display(), entries);
+                }
+                AuditCommand::Export { format } => {
+                    // A broken log is still worth exporting, to investigate it.
+                    if let Err(e) = audit.verify() {
+                        eprintln!("Warning: {}", e);
+                    }
+                    print!("{}", audit::export(&audit.entries()?, format));
+                }
+            }
+        }
+        Options::Watch { address, connection, ws_url, json } => {
+            let chain = connected()?;
+            let (balance, token_amounts) = (chain.balance(&address)?, chain.token_amounts(&address)?);
+            let ws_url = ws_url.unwrap_or_else(|| connection.websocket_url());
+            let watcher = watch::watch(&ws_url, &address, connection.commitment(), balance, token_amounts)?;
+            eprintln!("Watching {} on {}, the balance is {} SOL", address, ws_url, format_sol(balance));
+            for event in watcher.events() {
+                if json {
+                    println!("{}", event.to_json());
+                } else {
+                    println!("{}", event);
+                }
+            }
+            return Err(Error::SubscriptionClosed);
+        }
+        Options::Program {
+            step:
+                ProgramStep::StepTwo { keypair, session, first_messages, secret_state, encoding, policy, yes, operation },
+        } => {
+            require_verified(home, &session.keys)?;
+            let policy = policy.as_deref().map(Policy::read).transpose()?.unwrap_or_default();
+            let authority = tss::aggregate_keys(session.keys.clone(), session.label.as_deref())?.aggregate;
+            let message =
+                loader::message(&operation, &authority, session.fee_payer.as_ref(), session.recent_block_hash)?;
+            eprint!("{}", Review(&message));
+            let payer = session.fee_payer.unwrap_or(authority);
+            policy.check_loader(&message, loader::recipient(&operation, &payer).as_ref())?;
+            if !yes && !review::confirm("Sign this transaction?")? {
+                return Err(Error::SigningDeclined);
+            }
+            let nonces = AggMessage1 { sender: keypair.pubkey(), public_nonces: secret_state.public_nonces.clone() };
+            let event = Event::new(Action::PartialSignature, audit::nonce_session(&nonces), session.keys.clone());
+            let label = session.label.as_deref();
+            let sig = tss::sign_message(keypair, &session.keys, label, &message, first_messages, secret_state)?;
+            AuditLog::in_home(home).append(&event.with_message(&message))?;
+            println!("Partial signature: {}", sig.encode(encoding));
+        }
+        Options::Program {
+            step: ProgramStep::AggregateAndBroadcast { signatures, session, signers, connection, no_wait, operation },
         } => {
-            let sig = tss::step_two(keypair, amount, to, memo, recent_block_hash, keys, first_messages, secret_state)?;
-            println!("Partial signature: {}", sig.serialize_bs58());
-        }
-        Options::AggregateSignaturesAndBroadcast { signatures, amount, to, memo, recent_block_hash, net, keys } => {
-            let tx = tss::sign_and_broadcast(amount, to, memo, recent_block_hash, keys, signatures)?;
-            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
-            let sig = rpc_client.send_transaction(&tx).map_err(Error::SendTransactionFailed)?;
-            println!("Transaction ID: {}", sig);
-            rpc_client
-                .confirm_transaction_with_spinner(&sig, &recent_block_hash, rpc_client.commitment())
-                .map_err(Error::ConfirmingTransactionFailed)?;
+            require_verified(home, &session.keys)?;
+            let authority = tss::aggregate_keys(session.keys.clone(), session.label.as_deref())?.aggregate;
+            let message =
+                loader::message(&operation, &authority, session.fee_payer.as_ref(), session.recent_block_hash)?;
+            let signature = tss::aggregate_signatures(&session.keys, session.label.as_deref(), &message, signatures)?;
+            let tx = tss::assemble_transaction(message, &authority, signature, &signers)?;
+            let audit = AuditLog::in_home(home);
+            audit.append(&transaction_event(Action::Aggregation, &tx, session.keys.clone()))?;
+            let chain = connected()?;
+            let simulation = chain.simulate(&tx, true)?;
+            eprint!("{}", simulation);
+            simulation.into_result()?;
+            send_and_confirm(chain, &tx, connection.timeout(), no_wait)?;
+            audit.append(&transaction_event(Action::Broadcast, &tx, session.keys))?;
+            if let LoaderOperation::Write { program_file, .. } = &operation {
+                let next = loader::next_write_offset(&tx.message).expect("the message is a write");
+                let len = fs::metadata(program_file).map_err(|e| Error::FileFailed(program_file.clone(), e))?.len();
+                if (next as u64) < len {
+                    eprintln!("Wrote {} of {} bytes, continue with `--offset {}`", next, len, next);
+                } else {
+                    eprintln!("Wrote all {} bytes, the buffer is ready to deploy", len);
+                }
+            }
+        }
+        Options::SignTransaction { file, keypair, out, yes } => {
+            let mut tx = wire::read_file(&file)?;
+            eprint!("{}", Review(&tx.message));
+            if !yes && !review::confirm("Sign this transaction?")? {
+                return Err(Error::SigningDeclined);
+            }
+            tss::add_signer(&mut tx, &keypair)?;
+            let out = out.unwrap_or(file);
+            wire::write_file(&out, &tx)?;
+            eprintln!("Wrote the transaction signed by {} to {}", keypair.pubkey(), out.display());
+            report_missing_signers(&tx, tx.message.recent_blockhash);
+        }
+        Options::Verify { aggregate, keys, label, signed, signature } => {
+            let aggregate = match aggregate {
+                Some(aggregate) => aggregate,
+                None => tss::aggregate_keys(keys, label.as_deref())?.aggregate,
+            };
+            match signed.read()? {
+                (_, Some(tx)) => {
+                    for (signer, status) in verify::signature_statuses(&tx) {
+                        eprintln!("{}: {}", signer, status);
+                    }
+                    verify::verify_transaction(&tx, &aggregate)?;
+                }
+                (message, None) => {
+                    let signature = signature.expect("clap requires a signature along with a message");
+                    verify::verify_message(&aggregate, &message, &signature)?;
+                }
+            }
+            println!("The signature of {} is valid", aggregate);
+        }
+        Options::VerifyPartial { signature, signer, keys, label, first_messages, signed } => {
+            let (message, _) = signed.read()?;
+            tss::verify_partial_signature(&keys, label.as_deref(), &signer, &message, &first_messages, &signature)?;
+            println!("The partial signature of {} is valid", signer);
+        }
+        Options::Setup { step: SetupStep::Prove { keypair, keys } } => {
+            let proof = wallet::prove(&keypair, &keys)?;
+            println!("Proof: {}", proof);
+            eprintln!("Send it to every other party, to pass to `setup verify --proofs`");
+        }
+        Options::Setup { step: SetupStep::Verify { keys, proofs } } => {
+            let descriptor = wallet::verify(&keys, &proofs)?;
+            let path = WalletStore::in_home(home).save(&descriptor)?;
+            eprintln!("Every party proved it holds its share, wrote the wallet descriptor to {}", path.display());
+            println!("The Aggregated Public Key: {}", descriptor.aggregate);
         }
     }
     Ok(())
 }
 
-pub fn create_unsigned_transaction(amount: f64, to: &Pubkey, memo: Option<String>, payer: &Pubkey) -> Transaction {
-    let amount = native_token::sol_to_lamports(amount);
-    let transfer_ins = system_instruction::transfer(payer, to, amount);
-    let msg = match memo {
-        None => Message::new(&[transfer_ins], Some(payer)),
-        Some(memo) => {
-            let memo_ins = Instruction { program_id: spl_memo::id(), accounts: Vec::new(), data: memo.into_bytes() };
-            Message::new(&[transfer_ins, memo_ins], Some(payer))
-        }
-    };
-    Transaction::new_unsigned(msg)
+/// Keys used to be aggregated in the order they were listed, point at the address that gave in case funds are there.
+fn warn_legacy_order(keys: &[Pubkey]) -> Result<(), Error> {
+    if let Some(legacy) = tss::legacy_aggregate(keys)? {
+        eprintln!(
+            "Warning: the keys aren't in their canonical order. Versions that didn't sort the keys gave {} for this order, \
+             see \"Key order\" in the README if you have funds there",
+            legacy
+        );
+    }
+    Ok(())
+}
+
+/// Refuse key sets whose parties didn't all prove they hold their share, see `setup`.
+fn require_verified(home: &Path, keys: &[Pubkey]) -> Result<(), Error> {
+    WalletStore::in_home(home).require_verified(keys)
+}
+
+/// Tell which signatures a transaction still needs, or its ID once it's complete.
+fn report_missing_signers(tx: &Transaction, recent_block_hash: Hash) {
+    let missing = tss::missing_signers(tx);
+    if missing.is_empty() {
+        eprintln!("Transaction ID: {} (valid until blockhash {} expires)", tx.signatures[0], recent_block_hash);
+    } else {
+        let missing: Vec<_> = missing.iter().map(Pubkey::to_string).collect();
+        eprintln!("Still missing the signatures of: {}", missing.join(", "));
+    }
+}
+
+/// Send a signed transaction and, unless `no_wait` is set, wait until it either landed or its blockhash expired.
+fn send_and_confirm(chain: &dyn Chain, tx: &Transaction, timeout: Duration, no_wait: bool) -> Result<(), Error> {
+    if no_wait {
+        let sig = chain.send(tx)?;
+        println!("Transaction ID: {}", sig);
+        return Ok(());
+    }
+    println!("Transaction ID: {}", tx.signatures[0]);
+    let (_, slot) = submit::submit(chain, tx, timeout)?.into_result()?;
+    println!("Landed in slot {}", slot);
+    Ok(())
+}
+
+/// An audit event about a signed transaction, identified by the hash of its message.
+/// The transaction ID is the fee payer's signature, which is still missing when another party signs for it later.
+fn transaction_event(action: Action, tx: &Transaction, participants: Vec<Pubkey>) -> Event {
+    Event::new(action, audit::message_hash(&tx.message), participants).with_message(&tx.message)
+}
+
+/// Pick the transaction of the payout plan this signing session is about.
+fn session_params(
+    keys: Vec<Pubkey>,
+    recipients: &Recipients,
+    index: usize,
+    memo: Option<String>,
+    recent_block_hash: Hash,
+    label: Option<String>,
+    fee_payer: Option<Pubkey>,
+) -> Result<SessionParams, Error> {
+    let aggpubkey = tss::aggregate_keys(keys.clone(), label.as_deref())?.aggregate;
+    let mut batches = batch::plan(&recipients.payments()?, memo.as_deref(), &aggpubkey, fee_payer.as_ref())?;
+    if index >= batches.len() {
+        return Err(Error::BatchOutOfRange { index, batches: batches.len() });
+    }
+    let payments = batches.swap_remove(index);
+    Ok(SessionParams { keys, payments, memo, recent_block_hash, label, fee_payer })
+}
+
+/// Create a transfer of `amount` lamports from `payer` to `to`.
+pub fn create_unsigned_transaction(amount: u64, to: &Pubkey, memo: Option<String>, payer: &Pubkey) -> Transaction {
+    create_batch_transaction(&[Payment { to: *to, amount }], memo, payer)
+}
+
+/// Create a transaction making all the `payments` from `payer`.
+pub fn create_batch_transaction(payments: &[Payment], memo: Option<String>, payer: &Pubkey) -> Transaction {
+    Transaction::new_unsigned(Message::new(&batch_instructions(payments, memo, payer), Some(payer)))
+}
+
+/// The instructions making all the `payments` from `from`, followed by the memo if there is one.
+pub fn batch_instructions(payments: &[Payment], memo: Option<String>, from: &Pubkey) -> Vec<Instruction> {
+    let mut instructions: Vec<_> =
+        payments.iter().map(|payment| system_instruction::transfer(from, &payment.to, payment.amount)).collect();
+    if let Some(memo) = memo {
+        instructions.push(Instruction { program_id: spl_memo::id(), accounts: Vec::new(), data: memo.into_bytes() });
+    }
+    instructions
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use clap::Parser;
+    use solana_sdk::native_token::LAMPORTS_PER_SOL;
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::signature::{Keypair, Signer};
+    use solana_sdk::transaction::TransactionError;
+
+    use crate::audit::AuditLog;
+    use crate::batch::Payment;
+    use crate::chain::Chain;
+    use crate::cli::Options;
+    use crate::mock::MockChain;
+    use crate::policy::Policy;
+    use crate::serialization::{AggMessage1, Serialize};
+    use crate::tss::{self, SessionParams};
+    use crate::{run, wallet, Error};
+
+    const FEE: u64 = 5000;
+
+    fn run_args(chain: &MockChain, home: &Path, args: &[&str]) -> Result<(), Error> {
+        let opts = Options::try_parse_from([&["solana-tss"][..], args].concat()).unwrap();
+        run(opts, Some(chain as &dyn Chain), home)
+    }
+
+    fn command<'a>(name: &'a str, common: &[&'a str], extra: &[&'a str]) -> Vec<&'a str> {
+        [&[name][..], common, extra].concat()
+    }
+
+    /// Run the setup ceremony of the key set, as if every party sent its proof.
+    fn setup_wallet(chain: &MockChain, home: &Path, keys: &[Keypair]) {
+        let pubkeys: Vec<_> = keys.iter().map(Keypair::pubkey).collect();
+        let proofs: Vec<_> = keys.iter().map(|k| wallet::prove(k, &pubkeys).unwrap().to_string()).collect();
+        let pubkeys: Vec<_> = pubkeys.iter().map(Pubkey::to_string).collect();
+        let mut args = vec!["setup", "verify", "--keys"];
+        args.extend(pubkeys.iter().map(String::as_str));
+        args.push("--proofs");
+        args.extend(proofs.iter().map(String::as_str));
+        run_args(chain, home, &args).unwrap();
+    }
+
+    /// Run both signing steps for all parties and return the encoded partial signatures.
+    fn partial_signatures(keys: &[Keypair], session: &SessionParams) -> Vec<String> {
+        let clone = |k: &Keypair| Keypair::from_bytes(&k.to_bytes()).unwrap();
+        let (first_msgs, secrets): (Vec<_>, Vec<_>) = keys.iter().map(clone).map(tss::step_one).unzip();
+        let first_msgs: Vec<_> = first_msgs.iter().map(AggMessage1::serialize_bs58).collect();
+        keys.iter()
+            .zip(secrets)
+            .enumerate()
+            .map(|(i, (key, secret))| {
+                let others = first_msgs
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, _)| j != i)
+                    .map(|(_, msg)| AggMessage1::deserialize_bs58(msg).unwrap())
+                    .collect();
+                tss::step_two(clone(key), session, others, secret, &mut Policy::default()).unwrap().serialize_bs58()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_single_key_commands() {
+        let (chain, home_dir) = (MockChain::new(FEE), tempfile::tempdir().unwrap());
+        let home = home_dir.path();
+        let keypair = Keypair::new();
+        let (payer, to) = (keypair.pubkey(), Pubkey::new_unique());
+
+        run_args(&chain, home, &["airdrop", "--to", &payer.to_string(), "--amount", "2"]).unwrap();
+        assert_eq!(chain.balance(&payer).unwrap(), 2 * LAMPORTS_PER_SOL);
+        run_args(&chain, home, &["balance", &payer.to_string()]).unwrap();
+        run_args(&chain, home, &["recent-block-hash"]).unwrap();
+
+        let secret = keypair.to_base58_string();
+        run_args(&chain, home, &["send-single", "--keypair", &secret, "--to", &to.to_string(), "--amount", "1.5"])
+            .unwrap();
+        assert_eq!(chain.balance(&to).unwrap(), 3 * LAMPORTS_PER_SOL / 2);
+        assert_eq!(chain.balance(&payer).unwrap(), LAMPORTS_PER_SOL / 2 - FEE);
+
+        // The transfer fails on chain, but the fee is still paid.
+        let result =
+            run_args(&chain, home, &["send-single", "--keypair", &secret, "--to", &to.to_string(), "--amount", "1"]);
+        assert!(matches!(result, Err(Error::TransactionFailed { .. })));
+        assert_eq!(chain.balance(&payer).unwrap(), LAMPORTS_PER_SOL / 2 - 2 * FEE);
+    }
+
+    #[test]
+    fn test_missing_connection() {
+        let address = Pubkey::new_unique().to_string();
+        let opts = Options::try_parse_from(["solana-tss", "balance", &address]).unwrap();
+        let home_dir = tempfile::tempdir().unwrap();
+        assert!(matches!(run(opts, None, home_dir.path()), Err(Error::NotConnected)));
+    }
+
+    #[test]
+    fn test_aggregate_commands() {
+        let (chain, home_dir) = (MockChain::new(FEE), tempfile::tempdir().unwrap());
+        let home = home_dir.path();
+        let keys: Vec<_> = (0..3).map(|_| Keypair::new()).collect();
+        let pubkeys: Vec<_> = keys.iter().map(|k| k.pubkey()).collect();
+        let aggregate = tss::aggregate_keys(pubkeys.clone(), None).unwrap().aggregate;
+        chain.fund(&aggregate, 10 * LAMPORTS_PER_SOL);
+        let to = Pubkey::new_unique();
+        let session = SessionParams {
+            keys: pubkeys.clone(),
+            payments: vec![Payment { to, amount: LAMPORTS_PER_SOL }],
+            memo: None,
+            recent_block_hash: chain.latest_blockhash().unwrap(),
+            label: None,
+            fee_payer: None,
+        };
+        let signatures = partial_signatures(&keys, &session);
+
+        let pubkeys: Vec<_> = pubkeys.iter().map(Pubkey::to_string).collect();
+        let (to, hash) = (to.to_string(), session.recent_block_hash.to_string());
+        let mut common = vec!["--to", &to, "--amount", "1", "--recent-block-hash", &hash, "--keys"];
+        common.extend(pubkeys.iter().map(String::as_str));
+        common.push("--signatures");
+        common.extend(signatures.iter().map(String::as_str));
+
+        // Nothing is signed for a key set before every party proved it holds its share.
+        let result =
+            run_args(&chain, home, &command("aggregate-signatures-and-broadcast", &common, &["--simulate-only"]));
+        assert!(matches!(result, Err(Error::UnverifiedWallet(key)) if key == aggregate));
+        let mut aggregate_keys = vec!["aggregate-keys"];
+        aggregate_keys.extend(pubkeys.iter().map(String::as_str));
+        assert!(matches!(run_args(&chain, home, &aggregate_keys), Err(Error::UnverifiedWallet(_))));
+        setup_wallet(&chain, home, &keys);
+        run_args(&chain, home, &aggregate_keys).unwrap();
+
+        run_args(&chain, home, &command("aggregate-signatures-and-broadcast", &common, &["--simulate-only"])).unwrap();
+        assert_eq!(chain.balance(&session.payments[0].to).unwrap(), 0);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transfer.tx");
+        let out = path.to_str().unwrap();
+        run_args(&chain, home, &command("aggregate-signatures", &common, &["--out", out])).unwrap();
+        let aggregate_arg = aggregate.to_string();
+        run_args(&chain, home, &["verify", "--aggregate", &aggregate_arg, "--file", out]).unwrap();
+        let result = run_args(&chain, home, &["verify", "--aggregate", &to, "--file", out]);
+        assert!(matches!(result, Err(Error::NotASigner(_))));
+        run_args(&chain, home, &["broadcast", "--file", out]).unwrap();
+        assert_eq!(chain.balance(&session.payments[0].to).unwrap(), LAMPORTS_PER_SOL);
+        assert_eq!(chain.balance(&aggregate).unwrap(), 9 * LAMPORTS_PER_SOL - FEE);
+
+        // Once the block hash expired, the simulation refuses to send it.
+        chain.expire_blockhashes();
+        let result = run_args(&chain, home, &command("aggregate-signatures-and-broadcast", &common, &[]));
+        assert!(matches!(result, Err(Error::TransactionWouldFail(TransactionError::BlockhashNotFound))));
+
+        // A transaction that expires on its way isn't logged as broadcast, only the one that landed is.
+        let session = SessionParams { recent_block_hash: chain.latest_blockhash().unwrap(), ..session };
+        let signatures = partial_signatures(&keys, &session);
+        let hash = session.recent_block_hash.to_string();
+        let mut common = vec!["--to", &to, "--amount", "1", "--recent-block-hash", &hash, "--keys"];
+        common.extend(pubkeys.iter().map(String::as_str));
+        common.push("--signatures");
+        common.extend(signatures.iter().map(String::as_str));
+        chain.expire_after_sends(1);
+        let result = run_args(&chain, home, &command("aggregate-signatures-and-broadcast", &common, &[]));
+        assert!(matches!(result, Err(Error::TransactionExpired(_))));
+        let entries = AuditLog::in_home(home).entries().unwrap();
+        assert_eq!(entries.iter().filter(|entry| entry["action"] == "broadcast").count(), 1);
+    }
+
+    #[test]
+    fn test_fee_payer_commands() {
+        let (chain, home_dir) = (MockChain::new(FEE), tempfile::tempdir().unwrap());
+        let home = home_dir.path();
+        let keys: Vec<_> = (0..2).map(|_| Keypair::new()).collect();
+        let pubkeys: Vec<_> = keys.iter().map(|k| k.pubkey()).collect();
+        let aggregate = tss::aggregate_keys(pubkeys.clone(), None).unwrap().aggregate;
+        let hot_wallet = Keypair::new();
+        chain.fund(&aggregate, LAMPORTS_PER_SOL);
+        chain.fund(&hot_wallet.pubkey(), LAMPORTS_PER_SOL);
+        let to = Pubkey::new_unique();
+        let session = SessionParams {
+            keys: pubkeys.clone(),
+            payments: vec![Payment { to, amount: LAMPORTS_PER_SOL }],
+            memo: None,
+            recent_block_hash: chain.latest_blockhash().unwrap(),
+            label: None,
+            fee_payer: Some(hot_wallet.pubkey()),
+        };
+        let signatures = partial_signatures(&keys, &session);
+
+        let pubkeys: Vec<_> = pubkeys.iter().map(Pubkey::to_string).collect();
+        let (to, hash, fee_payer) =
+            (to.to_string(), session.recent_block_hash.to_string(), hot_wallet.pubkey().to_string());
+        let mut common = vec!["--to", &to, "--amount", "1", "--recent-block-hash", &hash, "--fee-payer", &fee_payer];
+        common.push("--keys");
+        common.extend(pubkeys.iter().map(String::as_str));
+        common.push("--signatures");
+        common.extend(signatures.iter().map(String::as_str));
+        setup_wallet(&chain, home, &keys);
+
+        // Without the fee payer's signature the transaction isn't complete.
+        let result = run_args(&chain, home, &command("aggregate-signatures-and-broadcast", &common, &[]));
+        assert!(matches!(result, Err(Error::MissingSigner(key)) if key == hot_wallet.pubkey()));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transfer.tx");
+        let out = path.to_str().unwrap();
+        run_args(&chain, home, &command("aggregate-signatures", &common, &["--out", out])).unwrap();
+        let result = run_args(&chain, home, &["broadcast", "--file", out]);
+        assert!(matches!(result, Err(Error::MissingSigner(key)) if key == hot_wallet.pubkey()));
+
+        let secret = hot_wallet.to_base58_string();
+        run_args(&chain, home, &["sign-transaction", "--file", out, "--keypair", &secret, "--yes"]).unwrap();
+        run_args(&chain, home, &["broadcast", "--file", out]).unwrap();
+        // The whole balance was sent, the hot wallet paid the fee for both signatures.
+        assert_eq!(chain.balance(&aggregate).unwrap(), 0);
+        assert_eq!(chain.balance(&hot_wallet.pubkey()).unwrap(), LAMPORTS_PER_SOL - 2 * FEE);
+    }
 }
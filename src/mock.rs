@@ -0,0 +1,246 @@
+//! An in-memory [`Chain`] that executes system transfers, so the commands can be tested quickly and deterministically.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_sdk::clock::{Slot, MAX_PROCESSING_AGE};
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::message::Message;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::rent::Rent;
+use solana_sdk::signature::Signature;
+use solana_sdk::system_instruction::SystemInstruction;
+use solana_sdk::system_program;
+use solana_sdk::transaction::{Transaction, TransactionError};
+
+use crate::chain::{Chain, Status};
+use crate::simulate::Simulation;
+use crate::Error;
+
+/// `SystemError::ResultWithNegativeLamports`, what the system program fails with on insufficient funds.
+const RESULT_WITH_NEGATIVE_LAMPORTS: u32 = 1;
+
+/// How an airdrop request goes wrong, see [`MockChain::fail_airdrops`].
+#[derive(Clone, Copy, Debug)]
+pub enum AirdropFailure {
+    /// The faucet refuses the request with a 429.
+    RateLimited,
+    /// The faucet accepts the request, but the cluster never hears of the transaction.
+    Lost,
+    /// The transaction lands, but fails.
+    Failed,
+}
+
+pub struct MockChain {
+    state: RefCell<State>,
+}
+
+struct State {
+    balances: HashMap<Pubkey, u64>,
+    valid_blockhashes: Vec<Hash>,
+    statuses: HashMap<Signature, Status>,
+    slot: Slot,
+    block_height: u64,
+    fee_per_signature: u64,
+    dropped_sends: usize,
+    sends_until_expiry: Option<usize>,
+    airdrop_failures: VecDeque<AirdropFailure>,
+}
+
+impl MockChain {
+    pub fn new(fee_per_signature: u64) -> Self {
+        let state = State {
+            balances: HashMap::new(),
+            valid_blockhashes: vec![Hash::new_unique()],
+            statuses: HashMap::new(),
+            slot: 1,
+            block_height: 1,
+            fee_per_signature,
+            dropped_sends: 0,
+            sends_until_expiry: None,
+            airdrop_failures: VecDeque::new(),
+        };
+        Self { state: RefCell::new(state) }
+    }
+
+    pub fn fund(&self, address: &Pubkey, lamports: u64) {
+        *self.state.borrow_mut().balances.entry(*address).or_default() += lamports;
+    }
+
+    /// Expire every block hash handed out so far.
+    pub fn expire_blockhashes(&self) {
+        self.state.borrow_mut().expire_blockhashes();
+    }
+
+    /// Expire every block hash handed out so far right before the `count`th next transaction is sent.
+    pub fn expire_after_sends(&self, count: usize) {
+        self.state.borrow_mut().sends_until_expiry = Some(count);
+    }
+
+    /// Make the next airdrop requests go wrong in these ways, one each, the ones after that succeed.
+    pub fn fail_airdrops(&self, failures: &[AirdropFailure]) {
+        self.state.borrow_mut().airdrop_failures = failures.iter().copied().collect();
+    }
+
+    /// Accept the next `count` sent transactions without ever landing them.
+    pub fn drop_sends(&self, count: usize) {
+        self.state.borrow_mut().dropped_sends = count;
+    }
+}
+
+impl State {
+    /// Finalize enough blocks that every valid hash is past its last valid block height.
+    fn expire_blockhashes(&mut self) {
+        self.valid_blockhashes = vec![Hash::new_unique()];
+        self.block_height += MAX_PROCESSING_AGE as u64 + 1;
+    }
+
+    fn balance(&self, address: &Pubkey) -> u64 {
+        self.balances.get(address).copied().unwrap_or(0)
+    }
+
+    fn fee(&self, message: &Message) -> u64 {
+        self.fee_per_signature * u64::from(message.header.num_required_signatures)
+    }
+
+    /// The balances after `tx`, or `None` if it can't land at all. Failed transactions still pay the fee.
+    fn execute(&self, tx: &Transaction) -> Option<(HashMap<Pubkey, u64>, Result<(), TransactionError>)> {
+        let message = &tx.message;
+        if !self.valid_blockhashes.contains(&message.recent_blockhash) {
+            return None;
+        }
+        let payer = message.account_keys[0];
+        let mut balances = self.balances.clone();
+        let payer_balance = balances.entry(payer).or_default();
+        *payer_balance = payer_balance.checked_sub(self.fee(message))?;
+        let after_fee = balances.clone();
+
+        for (i, instruction) in message.instructions.iter().enumerate() {
+            let program_id = message.account_keys[usize::from(instruction.program_id_index)];
+            let error = |e| Some((after_fee.clone(), Err(TransactionError::InstructionError(i as u8, e))));
+            if program_id == spl_memo::id() {
+                continue;
+            }
+            if program_id != system_program::id() {
+                return error(InstructionError::IncorrectProgramId);
+            }
+            let lamports = match bincode::deserialize(&instruction.data) {
+                Ok(SystemInstruction::Transfer { lamports }) => lamports,
+                _ => return error(InstructionError::InvalidInstructionData),
+            };
+            let from = message.account_keys[usize::from(instruction.accounts[0])];
+            let to = message.account_keys[usize::from(instruction.accounts[1])];
+            let from_balance = balances.entry(from).or_default();
+            match from_balance.checked_sub(lamports) {
+                Some(left) => *from_balance = left,
+                None => return error(InstructionError::Custom(RESULT_WITH_NEGATIVE_LAMPORTS)),
+            }
+            *balances.entry(to).or_default() += lamports;
+        }
+        Some((balances, Ok(())))
+    }
+}
+
+impl Chain for MockChain {
+    fn balance(&self, address: &Pubkey) -> Result<u64, Error> {
+        Ok(self.state.borrow().balance(address))
+    }
+
+    fn request_airdrop(&self, to: &Pubkey, lamports: u64) -> Result<Signature, Error> {
+        let mut state = self.state.borrow_mut();
+        let signature = Signature::new_unique();
+        let slot = state.slot;
+        match state.airdrop_failures.pop_front() {
+            Some(AirdropFailure::RateLimited) => {
+                let e = ClientErrorKind::Custom("HTTP status client error (429 Too Many Requests)".to_string());
+                return Err(Error::AirdropFailed(ClientError::from(e)));
+            }
+            Some(AirdropFailure::Lost) => return Ok(signature),
+            Some(AirdropFailure::Failed) => {
+                let err = Some(TransactionError::AccountNotFound);
+                state.statuses.insert(signature, Status { slot, err, committed: true });
+            }
+            None => {
+                *state.balances.entry(*to).or_default() += lamports;
+                state.statuses.insert(signature, Status { slot, err: None, committed: true });
+            }
+        }
+        state.slot += 1;
+        Ok(signature)
+    }
+
+    fn latest_blockhash(&self) -> Result<Hash, Error> {
+        Ok(*self.state.borrow().valid_blockhashes.last().unwrap())
+    }
+
+    fn last_valid_block_height(&self, hash: &Hash) -> Result<Option<u64>, Error> {
+        let state = self.state.borrow();
+        Ok(state.valid_blockhashes.contains(hash).then(|| state.block_height + MAX_PROCESSING_AGE as u64))
+    }
+
+    fn finalized_block_height(&self) -> Result<u64, Error> {
+        Ok(self.state.borrow().block_height)
+    }
+
+    /// Like a real cluster, invalid transactions are silently dropped.
+    fn send(&self, tx: &Transaction) -> Result<Signature, Error> {
+        let signature = tx.signatures[0];
+        let mut state = self.state.borrow_mut();
+        if let Some(left) = state.sends_until_expiry.as_mut() {
+            *left -= 1;
+            if *left == 0 {
+                state.sends_until_expiry = None;
+                state.expire_blockhashes();
+            }
+        }
+        if state.statuses.contains_key(&signature) || tx.verify().is_err() {
+            return Ok(signature);
+        }
+        if state.dropped_sends > 0 {
+            state.dropped_sends -= 1;
+            return Ok(signature);
+        }
+        if let Some((balances, result)) = state.execute(tx) {
+            state.balances = balances;
+            let slot = state.slot;
+            state.statuses.insert(signature, Status { slot, err: result.err(), committed: true });
+            state.slot += 1;
+        }
+        Ok(signature)
+    }
+
+    fn status(&self, signature: &Signature) -> Result<Option<Status>, Error> {
+        Ok(self.state.borrow().statuses.get(signature).cloned())
+    }
+
+    fn simulate(&self, tx: &Transaction, sig_verify: bool) -> Result<Simulation, Error> {
+        let err = if sig_verify && tx.verify().is_err() {
+            Some(TransactionError::SignatureFailure)
+        } else {
+            let state = self.state.borrow();
+            match state.execute(tx) {
+                Some((_, result)) => result.err(),
+                None if state.valid_blockhashes.contains(&tx.message.recent_blockhash) => {
+                    Some(TransactionError::InsufficientFundsForFee)
+                }
+                None => Some(TransactionError::BlockhashNotFound),
+            }
+        };
+        Ok(Simulation { err, logs: Vec::new(), units_consumed: None })
+    }
+
+    fn fee_for_message(&self, message: &Message) -> Result<u64, Error> {
+        Ok(self.state.borrow().fee(message))
+    }
+
+    fn minimum_balance_for_rent_exemption(&self, data_len: usize) -> Result<u64, Error> {
+        Ok(Rent::default().minimum_balance(data_len))
+    }
+
+    /// Only system accounts exist here.
+    fn token_amounts(&self, _owner: &Pubkey) -> Result<HashMap<Pubkey, u64>, Error> {
+        Ok(HashMap::new())
+    }
+}
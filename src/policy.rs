@@ -0,0 +1,384 @@
+use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use regex::Regex;
+use serde_json::{json, Value};
+use solana_sdk::message::Message;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::transaction::Transaction;
+
+use crate::audit;
+use crate::cli::{format_sol, parse_sol};
+use crate::tss::SessionParams;
+use crate::Error;
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// The rules of a [`Policy`], named after their JSON keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rule {
+    MaxAmount,
+    DailyLimit,
+    AllowedRecipients,
+    AllowedPrograms,
+    MemoPattern,
+}
+
+impl Rule {
+    const ALL: [Rule; 5] =
+        [Self::MaxAmount, Self::DailyLimit, Self::AllowedRecipients, Self::AllowedPrograms, Self::MemoPattern];
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::MaxAmount => "max_amount",
+            Self::DailyLimit => "daily_limit",
+            Self::AllowedRecipients => "allowed_recipients",
+            Self::AllowedPrograms => "allowed_programs",
+            Self::MemoPattern => "memo_pattern",
+        })
+    }
+}
+
+/// Local limits on what a party agrees to sign, read from a JSON file like:
+/// ```json
+/// {
+///     "max_amount": "10",
+///     "daily_limit": "50",
+///     "allowed_recipients": ["<ADDRESS>", ...],
+///     "allowed_programs": ["<PROGRAM_ID>", ...],
+///     "memo_pattern": "invoice-[0-9]+"
+/// }
+/// ```
+/// Amounts are in SOL, the memo pattern has to match the whole memo, and a memo is required if there is a pattern.
+/// Every rule is optional, an empty policy allows everything, and an unknown rule is an error.
+/// The daily limit is over the last 24 hours, what was signed is kept in a state file next to the policy. Processes
+/// sharing the policy, like the cosigner and the CLI, lock the state while they check and record against it.
+#[derive(Debug, Default)]
+pub struct Policy {
+    /// The most lamports a single transaction may move.
+    pub max_amount: Option<u64>,
+    /// The most lamports signed for in any 24 hours.
+    pub daily_limit: Option<u64>,
+    /// If set, payments may only go to these addresses.
+    pub allowed_recipients: Option<HashSet<Pubkey>>,
+    /// If set, the transaction may only call these programs.
+    pub allowed_programs: Option<HashSet<Pubkey>>,
+    pub memo_pattern: Option<Regex>,
+    /// When and how many lamports were signed for, oldest first.
+    history: Vec<(u64, u64)>,
+    state_file: Option<PathBuf>,
+}
+
+impl Policy {
+    /// Read the policy at `path`, and the daily limit's state from `<path>.state` if it exists.
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path).map_err(|e| Error::FileFailed(path.to_path_buf(), e))?;
+        let mut policy =
+            Self::from_json(&serde_json::from_str(&content).map_err(|e| Error::InvalidPolicy(e.to_string()))?)?;
+        let state_file = PathBuf::from(format!("{}.state", path.display()));
+        policy.history = read_history(&state_file)?;
+        policy.state_file = Some(state_file);
+        Ok(policy)
+    }
+
+    /// Fails on anything but an object of known rules, so a misspelled rule can't silently switch a limit off.
+    pub fn from_json(value: &Value) -> Result<Self, Error> {
+        let rules = value.as_object().ok_or_else(|| Error::InvalidPolicy("the policy isn't an object".to_string()))?;
+        if let Some(key) = rules.keys().find(|key| !Rule::ALL.iter().any(|rule| rule.to_string() == **key)) {
+            return Err(Error::InvalidPolicy(format!("unknown rule {:?}", key)));
+        }
+        let invalid = |rule: Rule| Error::InvalidPolicy(format!("invalid {}", rule));
+        let amount = |rule: Rule| match &value[rule.to_string()] {
+            Value::Null => Ok(None),
+            Value::String(amount) => parse_sol(amount).map(Some),
+            _ => Err(invalid(rule)),
+        };
+        let addresses = |rule: Rule| match &value[rule.to_string()] {
+            Value::Null => Ok(None),
+            Value::Array(addresses) => addresses
+                .iter()
+                .map(|address| address.as_str().and_then(|a| a.parse().ok()).ok_or_else(|| invalid(rule)))
+                .collect::<Result<HashSet<Pubkey>, Error>>()
+                .map(Some),
+            _ => Err(invalid(rule)),
+        };
+        let memo_pattern = match &value[Rule::MemoPattern.to_string()] {
+            Value::Null => None,
+            Value::String(pattern) => {
+                Some(Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| Error::InvalidPolicy(e.to_string()))?)
+            }
+            _ => return Err(invalid(Rule::MemoPattern)),
+        };
+        Ok(Self {
+            max_amount: amount(Rule::MaxAmount)?,
+            daily_limit: amount(Rule::DailyLimit)?,
+            allowed_recipients: addresses(Rule::AllowedRecipients)?,
+            allowed_programs: addresses(Rule::AllowedPrograms)?,
+            memo_pattern,
+            history: Vec::new(),
+            state_file: None,
+        })
+    }
+
+    /// Fail with `PolicyViolation` if signing `tx`, which makes the `session`'s payments, breaks any rule.
+    pub fn check(&mut self, session: &SessionParams, tx: &Transaction) -> Result<(), Error> {
+        let _lock = self.lock_state()?;
+        self.check_at(session, tx, now())
+    }
+
+    /// Fail with `PolicyViolation` if signing a loader operation breaks a rule. It makes no payments, so only the
+    /// programs it calls and where it sends the lamports it frees up are checked, and a memo pattern always refuses it.
+    pub fn check_loader(&self, message: &Message, recipient: Option<&Pubkey>) -> Result<(), Error> {
+        self.check_recipients(recipient.into_iter())?;
+        self.check_programs(message)?;
+        self.check_memo(None)
+    }
+
+    /// Count `session` towards the daily limit, once it's signed.
+    pub fn record(&mut self, session: &SessionParams) -> Result<(), Error> {
+        let _lock = self.lock_state()?;
+        self.record_at(session, now())
+    }
+
+    /// Lock the daily limit's state until the returned file is closed, and reload the history another process may
+    /// have added to. The lock is a file of its own, so the state can still be replaced in one rename.
+    fn lock_state(&mut self) -> Result<Option<File>, Error> {
+        let state_file = match (&self.state_file, self.daily_limit) {
+            (Some(state_file), Some(_)) => state_file.clone(),
+            _ => return Ok(None),
+        };
+        let lock_file = PathBuf::from(format!("{}.lock", state_file.display()));
+        let lock = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&lock_file)
+            .and_then(|file| audit::lock_exclusive(&file).map(|()| file))
+            .map_err(|e| Error::FileFailed(lock_file, e))?;
+        self.history = read_history(&state_file)?;
+        Ok(Some(lock))
+    }
+
+    fn check_at(&self, session: &SessionParams, tx: &Transaction, now: u64) -> Result<(), Error> {
+        let violation = |rule, reason| Err(Error::PolicyViolation { rule, reason });
+        let total = total(session);
+        if let Some(max_amount) = self.max_amount {
+            if total > max_amount {
+                let reason =
+                    format!("{} SOL is more than the maximum of {} SOL", format_sol(total), format_sol(max_amount));
+                return violation(Rule::MaxAmount, reason);
+            }
+        }
+        if let Some(daily_limit) = self.daily_limit {
+            let spent = self.spent_since(now.saturating_sub(DAY_SECS));
+            if spent.saturating_add(total) > daily_limit {
+                let reason = format!(
+                    "{} SOL were already signed for in the last 24 hours, {} more would exceed the limit of {} SOL",
+                    format_sol(spent),
+                    format_sol(total),
+                    format_sol(daily_limit)
+                );
+                return violation(Rule::DailyLimit, reason);
+            }
+        }
+        self.check_recipients(session.payments.iter().map(|payment| &payment.to))?;
+        self.check_programs(&tx.message)?;
+        self.check_memo(session.memo.as_deref())
+    }
+
+    fn check_recipients<'a>(&self, mut recipients: impl Iterator<Item = &'a Pubkey>) -> Result<(), Error> {
+        if let Some(allowed) = &self.allowed_recipients {
+            if let Some(recipient) = recipients.find(|recipient| !allowed.contains(recipient)) {
+                let reason = format!("{} isn't an allowed recipient", recipient);
+                return Err(Error::PolicyViolation { rule: Rule::AllowedRecipients, reason });
+            }
+        }
+        Ok(())
+    }
+
+    fn check_programs(&self, message: &Message) -> Result<(), Error> {
+        if let Some(allowed) = &self.allowed_programs {
+            let mut programs =
+                message.instructions.iter().map(|ix| message.account_keys[usize::from(ix.program_id_index)]);
+            if let Some(program) = programs.find(|program| !allowed.contains(program)) {
+                let reason = format!("{} isn't an allowed program", program);
+                return Err(Error::PolicyViolation { rule: Rule::AllowedPrograms, reason });
+            }
+        }
+        Ok(())
+    }
+
+    fn check_memo(&self, memo: Option<&str>) -> Result<(), Error> {
+        let reason = match (&self.memo_pattern, memo) {
+            (None, _) => return Ok(()),
+            (Some(pattern), Some(memo)) if pattern.is_match(memo) => return Ok(()),
+            (Some(_), Some(memo)) => format!("{:?} doesn't match the pattern", memo),
+            (Some(_), None) => "a memo is required".to_string(),
+        };
+        Err(Error::PolicyViolation { rule: Rule::MemoPattern, reason })
+    }
+
+    fn record_at(&mut self, session: &SessionParams, now: u64) -> Result<(), Error> {
+        if self.daily_limit.is_none() {
+            return Ok(());
+        }
+        self.history.retain(|&(time, _)| time > now.saturating_sub(DAY_SECS));
+        self.history.push((now, total(session)));
+        if let Some(state_file) = &self.state_file {
+            let history: Vec<_> = self.history.iter().map(|&(time, lamports)| json!([time, lamports])).collect();
+            // Write a new file and move it into place, so a crash can't leave a truncated state behind.
+            let tmp = PathBuf::from(format!("{}.tmp", state_file.display()));
+            fs::write(&tmp, Value::from(history).to_string())
+                .and_then(|()| fs::rename(&tmp, state_file))
+                .map_err(|e| Error::FileFailed(state_file.clone(), e))?;
+        }
+        Ok(())
+    }
+
+    fn spent_since(&self, since: u64) -> u64 {
+        self.history
+            .iter()
+            .filter(|&&(time, _)| time > since)
+            .fold(0, |spent, &(_, lamports)| spent.saturating_add(lamports))
+    }
+}
+
+fn total(session: &SessionParams) -> u64 {
+    session.payments.iter().fold(0u64, |total, payment| total.saturating_add(payment.amount))
+}
+
+fn read_history(state_file: &Path) -> Result<Vec<(u64, u64)>, Error> {
+    if !state_file.exists() {
+        return Ok(Vec::new());
+    }
+    let state = fs::read_to_string(state_file).map_err(|e| Error::FileFailed(state_file.to_path_buf(), e))?;
+    serde_json::from_str::<Value>(&state)
+        .ok()
+        .and_then(|state| parse_history(&state))
+        .ok_or_else(|| Error::InvalidPolicy(format!("corrupted state in {}", state_file.display())))
+}
+
+fn parse_history(state: &Value) -> Option<Vec<(u64, u64)>> {
+    state.as_array()?.iter().map(|entry| Some((entry.get(0)?.as_u64()?, entry.get(1)?.as_u64()?))).collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use solana_sdk::hash::Hash;
+    use solana_sdk::native_token::LAMPORTS_PER_SOL;
+    use solana_sdk::pubkey::Pubkey;
+
+    use crate::batch::Payment;
+    use crate::policy::{Policy, Rule, DAY_SECS};
+    use crate::tss::{session_transaction, SessionParams};
+    use crate::Error;
+
+    fn session(payments: Vec<Payment>, memo: Option<&str>) -> SessionParams {
+        SessionParams {
+            keys: vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            payments,
+            memo: memo.map(str::to_string),
+            recent_block_hash: Hash::new_unique(),
+            label: None,
+            fee_payer: None,
+        }
+    }
+
+    fn violated_rule(policy: &Policy, session: &SessionParams, now: u64) -> Option<Rule> {
+        match policy.check_at(session, &session_transaction(session).unwrap(), now) {
+            Ok(()) => None,
+            Err(Error::PolicyViolation { rule, .. }) => Some(rule),
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_policy_rules() {
+        let (allowed, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let policy = Policy::from_json(&json!({
+            "max_amount": "1.5",
+            "allowed_recipients": [allowed.to_string()],
+            "allowed_programs": [solana_sdk::system_program::id().to_string(), spl_memo::id().to_string()],
+            "memo_pattern": "invoice-[0-9]+",
+        }))
+        .unwrap();
+        let half = LAMPORTS_PER_SOL / 2;
+        let memo = Some("invoice-42");
+
+        assert_eq!(violated_rule(&policy, &session(vec![Payment { to: allowed, amount: half }; 3], memo), 0), None);
+        let too_much = session(vec![Payment { to: allowed, amount: half }; 4], memo);
+        assert_eq!(violated_rule(&policy, &too_much, 0), Some(Rule::MaxAmount));
+        let not_allowed = session(vec![Payment { to: allowed, amount: half }, Payment { to: other, amount: 1 }], memo);
+        assert_eq!(violated_rule(&policy, &not_allowed, 0), Some(Rule::AllowedRecipients));
+        let payments = vec![Payment { to: allowed, amount: half }];
+        assert_eq!(violated_rule(&policy, &session(payments.clone(), None), 0), Some(Rule::MemoPattern));
+        let sneaky = session(payments.clone(), Some("invoice-42; and something else"));
+        assert_eq!(violated_rule(&policy, &sneaky, 0), Some(Rule::MemoPattern));
+
+        let memo_only = Policy::from_json(&json!({ "allowed_programs": [spl_memo::id().to_string()] })).unwrap();
+        assert_eq!(violated_rule(&memo_only, &session(payments, memo), 0), Some(Rule::AllowedPrograms));
+
+        assert_eq!(violated_rule(&Policy::from_json(&json!({})).unwrap(), &not_allowed, 0), None);
+        let invalid_policies = [
+            json!({"max_amount": 1}),
+            json!({"allowed_recipients": ["abc"]}),
+            json!({"memo_pattern": "("}),
+            json!({"daily-limit": "1"}),
+            json!({"maxAmount": "1"}),
+            json!(["max_amount"]),
+            json!("max_amount"),
+        ];
+        for invalid in invalid_policies {
+            assert!(matches!(Policy::from_json(&invalid), Err(Error::InvalidPolicy(_))), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_daily_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.json");
+        std::fs::write(&path, json!({ "daily_limit": "2" }).to_string()).unwrap();
+        let mut policy = Policy::read(&path).unwrap();
+        let one = session(vec![Payment { to: Pubkey::new_unique(), amount: LAMPORTS_PER_SOL }], None);
+        let start = 1_000_000;
+
+        for hour in 0..2 {
+            let now = start + hour * 3600;
+            assert_eq!(violated_rule(&policy, &one, now), None);
+            policy.record_at(&one, now).unwrap();
+        }
+        assert_eq!(violated_rule(&policy, &one, start + 7200), Some(Rule::DailyLimit));
+        // The state survives restarts.
+        let mut policy = Policy::read(&path).unwrap();
+        assert_eq!(violated_rule(&policy, &one, start + 7200), Some(Rule::DailyLimit));
+        // A day after the first signature, there is room for one more.
+        assert_eq!(violated_rule(&policy, &one, start + DAY_SECS), None);
+        policy.record_at(&one, start + DAY_SECS).unwrap();
+        assert_eq!(violated_rule(&policy, &one, start + DAY_SECS), Some(Rule::DailyLimit));
+    }
+
+    #[test]
+    fn test_shared_daily_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.json");
+        std::fs::write(&path, json!({ "daily_limit": "1.5" }).to_string()).unwrap();
+        // Like the cosigner and the CLI, both loaded before either signed.
+        let (mut daemon, mut cli) = (Policy::read(&path).unwrap(), Policy::read(&path).unwrap());
+        let one = session(vec![Payment { to: Pubkey::new_unique(), amount: LAMPORTS_PER_SOL }], None);
+        let tx = session_transaction(&one).unwrap();
+
+        daemon.check(&one, &tx).unwrap();
+        cli.check(&one, &tx).unwrap();
+        daemon.record(&one).unwrap();
+        assert!(matches!(cli.check(&one, &tx), Err(Error::PolicyViolation { rule: Rule::DailyLimit, .. })));
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::transaction::Transaction;
+
+use crate::batch::Payment;
+use crate::chain::Chain;
+use crate::Error;
+
+/// What we learned about the chain state before signing.
+#[derive(Debug)]
+pub struct Preflight {
+    pub balance: u64,
+    pub fee: u64,
+}
+
+/// Make sure `tx`, which makes the `payments` from `from`, can actually land before anyone signs it.
+/// Fails if `from` can't cover the payments, or the fee if it pays it too, if a separate fee payer can't cover the fee,
+/// or if a recipient, `from` or the fee payer would end up with a balance the runtime rejects: above zero, but below the
+/// rent exemption.
+pub fn check(chain: &dyn Chain, tx: &Transaction, from: &Pubkey, payments: &[Payment]) -> Result<Preflight, Error> {
+    let payer = tx.message.account_keys[0];
+    let fee = chain.fee_for_message(&tx.message)?;
+    let balance = chain.balance(from)?;
+    let rent_exempt_minimum = chain.minimum_balance_for_rent_exemption(0)?;
+
+    let fee_from_source = if payer == *from { fee } else { 0 };
+    let needed = payments.iter().fold(fee_from_source, |needed, payment| needed.saturating_add(payment.amount));
+    if balance < needed {
+        return Err(Error::InsufficientFunds { address: *from, balance, needed });
+    }
+    let remaining = balance - needed;
+    if remaining != 0 && remaining < rent_exempt_minimum {
+        return Err(Error::NotRentExempt { address: *from, balance: remaining, minimum: rent_exempt_minimum });
+    }
+    if payer != *from {
+        let payer_balance = chain.balance(&payer)?;
+        if payer_balance < fee {
+            return Err(Error::InsufficientFunds { address: payer, balance: payer_balance, needed: fee });
+        }
+        let payer_remaining = payer_balance - fee;
+        if payer_remaining != 0 && payer_remaining < rent_exempt_minimum {
+            return Err(Error::NotRentExempt {
+                address: payer,
+                balance: payer_remaining,
+                minimum: rent_exempt_minimum,
+            });
+        }
+    }
+
+    // The same recipient can show up more than once.
+    let mut received: HashMap<Pubkey, u64> = HashMap::new();
+    for payment in payments {
+        *received.entry(payment.to).or_default() += payment.amount;
+    }
+    for payment in payments {
+        let recipient_balance = chain.balance(&payment.to)?;
+        let recipient_after = recipient_balance.saturating_add(received[&payment.to]);
+        if recipient_after < rent_exempt_minimum {
+            return Err(Error::NotRentExempt {
+                address: payment.to,
+                balance: recipient_after,
+                minimum: rent_exempt_minimum,
+            });
+        }
+    }
+
+    Ok(Preflight { balance, fee })
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::message::Message;
+    use solana_sdk::native_token::LAMPORTS_PER_SOL;
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::transaction::Transaction;
+    use solana_streamer::socket::SocketAddrSpace;
+    use solana_test_validator::TestValidator;
+
+    use crate::batch::Payment;
+    use crate::preflight::check;
+    use crate::{batch_instructions, create_batch_transaction, Error};
+
+    #[test]
+    fn test_preflight() {
+        let payer = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        let fee = 5000;
+        let testnet = TestValidator::with_custom_fees(payer, fee, None, SocketAddrSpace::Unspecified);
+        let rpc_client = testnet.get_rpc_client();
+        let balance = rpc_client.get_balance(&payer).unwrap();
+
+        let check_payments = |payments: &[Payment]| {
+            let mut tx = create_batch_transaction(payments, None, &payer);
+            tx.message.recent_blockhash = rpc_client.get_latest_blockhash().unwrap();
+            check(&rpc_client, &tx, &payer, payments)
+        };
+
+        let preflight = check_payments(&[Payment { to, amount: LAMPORTS_PER_SOL }]).unwrap();
+        assert_eq!(preflight.balance, balance);
+        assert_eq!(preflight.fee, fee);
+
+        match check_payments(&[Payment { to, amount: balance }]) {
+            Err(Error::InsufficientFunds { address, balance: found, .. }) => {
+                assert_eq!(address, payer);
+                assert_eq!(found, balance);
+            }
+            other => panic!("expected InsufficientFunds, got: {:?}", other),
+        }
+
+        match check_payments(&[Payment { to, amount: 1 }]) {
+            Err(Error::NotRentExempt { address, balance: 1, .. }) => assert_eq!(address, to),
+            other => panic!("expected NotRentExempt, got: {:?}", other),
+        }
+
+        // Sending everything is fine, leaving less than the rent exemption isn't.
+        let rent_exempt_minimum = rpc_client.get_minimum_balance_for_rent_exemption(0).unwrap();
+        check_payments(&[Payment { to, amount: balance - fee }]).unwrap();
+        match check_payments(&[Payment { to, amount: balance - fee - rent_exempt_minimum + 1 }]) {
+            Err(Error::NotRentExempt { address, .. }) => assert_eq!(address, payer),
+            other => panic!("expected NotRentExempt, got: {:?}", other),
+        }
+
+        // Splitting the amount between payments shouldn't change the result.
+        let half = LAMPORTS_PER_SOL / 2;
+        check_payments(&[Payment { to, amount: half }, Payment { to, amount: half }]).unwrap();
+        match check_payments(&[Payment { to, amount: balance / 2 }, Payment { to, amount: balance / 2 + 1 }]) {
+            Err(Error::InsufficientFunds { .. }) => {}
+            other => panic!("expected InsufficientFunds, got: {:?}", other),
+        }
+
+        // With a separate fee payer the whole balance can be sent, but the fee payer has to cover the fee.
+        let fee_payer = Pubkey::new_unique();
+        let payments = [Payment { to, amount: balance }];
+        let message = Message::new(&batch_instructions(&payments, None, &payer), Some(&fee_payer));
+        let mut tx = Transaction::new_unsigned(message);
+        tx.message.recent_blockhash = rpc_client.get_latest_blockhash().unwrap();
+        match check(&rpc_client, &tx, &payer, &payments) {
+            Err(Error::InsufficientFunds { address, balance: 0, needed }) => {
+                assert_eq!(address, fee_payer);
+                assert_eq!(needed, 2 * fee);
+            }
+            other => panic!("expected InsufficientFunds, got: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! A human readable rendering of the exact message a party is about to sign.
+
+use std::convert::TryInto;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, BufRead, Write};
+
+use solana_sdk::hash::hash;
+use solana_sdk::instruction::CompiledInstruction;
+use solana_sdk::loader_upgradeable_instruction::UpgradeableLoaderInstruction;
+use solana_sdk::message::Message;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::stake::instruction::StakeInstruction;
+use solana_sdk::system_instruction::SystemInstruction;
+use solana_sdk::{bpf_loader_upgradeable, stake, system_program};
+
+use crate::cli::format_sol;
+use crate::Error;
+
+pub const TOKEN_PROGRAM_ID: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+const COMPUTE_BUDGET_PROGRAM_ID: &str = "ComputeBudget111111111111111111111111111111";
+
+pub struct Review<'a>(pub &'a Message);
+
+impl Display for Review<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let message = self.0;
+        writeln!(f, "Fee payer: {}", message.account_keys[0])?;
+        writeln!(f, "Recent block hash: {}", message.recent_blockhash)?;
+        writeln!(f, "Instructions:")?;
+        for (i, instruction) in message.instructions.iter().enumerate() {
+            writeln!(f, "  {}. {}", i + 1, describe(message, instruction))?;
+        }
+        writeln!(f, "Message fingerprint: {}", fingerprint(message))
+    }
+}
+
+/// A short digest of the serialized message, all parties should see the same one before signing.
+pub fn fingerprint(message: &Message) -> String {
+    let hash = hash(&message.serialize());
+    let hex = hex::encode(&hash.as_ref()[..8]);
+    let groups: Vec<_> = hex.as_bytes().chunks(4).map(|group| std::str::from_utf8(group).unwrap()).collect();
+    groups.join("-")
+}
+
+/// Ask on stderr whether to go on, anything but `y`/`yes` (including no answer at all) declines.
+pub fn confirm(question: &str) -> Result<bool, Error> {
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush().map_err(Error::PromptFailed)?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer).map_err(Error::PromptFailed)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+fn describe(message: &Message, instruction: &CompiledInstruction) -> String {
+    let program_id = message.account_keys[usize::from(instruction.program_id_index)];
+    let account = |i: usize| {
+        instruction
+            .accounts
+            .get(i)
+            .and_then(|&index| message.account_keys.get(usize::from(index)))
+            .map_or_else(|| "<missing account>".to_string(), Pubkey::to_string)
+    };
+    let data = &instruction.data;
+
+    if program_id == system_program::id() {
+        let description = match bincode::deserialize::<SystemInstruction>(data) {
+            Ok(SystemInstruction::Transfer { lamports }) => {
+                format!("transfer {} SOL from {} to {}", format_sol(lamports), account(0), account(1))
+            }
+            Ok(SystemInstruction::CreateAccount { lamports, space, owner }) => format!(
+                "create account {} funded by {} with {} SOL and {} bytes, owned by {}",
+                account(1),
+                account(0),
+                format_sol(lamports),
+                space,
+                owner
+            ),
+            Ok(other) => format!("{:?}", other),
+            Err(_) => format!("undecodable instruction ({} bytes)", data.len()),
+        };
+        format!("System: {}", description)
+    } else if program_id == spl_memo::id() {
+        match std::str::from_utf8(data) {
+            Ok(memo) => format!("Memo: {:?}", memo),
+            Err(_) => format!("Memo: invalid UTF-8 ({} bytes)", data.len()),
+        }
+    } else if program_id.to_string() == TOKEN_PROGRAM_ID {
+        format!("Token: {}", describe_token(data, account))
+    } else if program_id == bpf_loader_upgradeable::id() {
+        let description = match bincode::deserialize::<UpgradeableLoaderInstruction>(data) {
+            Ok(UpgradeableLoaderInstruction::Write { offset, bytes }) => {
+                format!("write {} bytes at offset {} into buffer {}", bytes.len(), offset, account(0))
+            }
+            Ok(UpgradeableLoaderInstruction::DeployWithMaxDataLen { max_data_len }) => format!(
+                "deploy program {} from buffer {} with a maximum length of {} bytes, upgrade authority {}",
+                account(2),
+                account(3),
+                max_data_len,
+                account(7)
+            ),
+            Ok(UpgradeableLoaderInstruction::Upgrade) => format!(
+                "upgrade program {} from buffer {}, spilling to {}, authority {}",
+                account(1),
+                account(2),
+                account(3),
+                account(6)
+            ),
+            Ok(UpgradeableLoaderInstruction::SetAuthority) if instruction.accounts.len() > 2 => {
+                format!("set the authority of {} from {} to {}", account(0), account(1), account(2))
+            }
+            Ok(UpgradeableLoaderInstruction::SetAuthority) => {
+                format!("make {} final, its authority {} is removed for good", account(0), account(1))
+            }
+            Ok(UpgradeableLoaderInstruction::Close) => {
+                format!("close {} sending its lamports to {}, authority {}", account(0), account(1), account(2))
+            }
+            Ok(other) => format!("{:?}", other),
+            Err(_) => format!("undecodable instruction ({} bytes)", data.len()),
+        };
+        format!("Upgradeable loader: {}", description)
+    } else if program_id == stake::program::id() {
+        match bincode::deserialize::<StakeInstruction>(data) {
+            Ok(decoded) => {
+                let accounts: Vec<_> = (0..instruction.accounts.len()).map(account).collect();
+                format!("Stake: {:?}, accounts: {}", decoded, accounts.join(", "))
+            }
+            Err(_) => format!("Stake: undecodable instruction ({} bytes)", data.len()),
+        }
+    } else if program_id.to_string() == COMPUTE_BUDGET_PROGRAM_ID {
+        format!("Compute budget: {}", describe_compute_budget(data))
+    } else {
+        let accounts: Vec<_> = (0..instruction.accounts.len()).map(account).collect();
+        format!(
+            "Unknown program {}: {} bytes of data ({}), accounts: {}",
+            program_id,
+            data.len(),
+            hex::encode(data),
+            accounts.join(", ")
+        )
+    }
+}
+
+/// The token program isn't a dependency, so decode the handful of instructions a wallet would sign by their tag.
+fn describe_token(data: &[u8], account: impl Fn(usize) -> String) -> String {
+    let amount = || data.get(1..9).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()));
+    match (data.first(), amount()) {
+        (Some(3), Some(amount)) => format!("transfer {} base units from {} to {}", amount, account(0), account(1)),
+        (Some(4), Some(amount)) => format!("approve {} to spend {} base units of {}", account(1), amount, account(0)),
+        (Some(7), Some(amount)) => format!("mint {} base units of {} to {}", amount, account(0), account(1)),
+        (Some(8), Some(amount)) => format!("burn {} base units from {}", amount, account(0)),
+        (Some(9), _) => format!("close {}, sending its lamports to {}", account(0), account(1)),
+        (Some(12), Some(amount)) if data.len() == 10 => format!(
+            "transfer {} base units ({} decimals) of mint {} from {} to {}",
+            amount,
+            data[9],
+            account(1),
+            account(0),
+            account(2)
+        ),
+        (Some(tag), _) => format!("instruction {} ({} bytes)", tag, data.len()),
+        (None, _) => "empty instruction".to_string(),
+    }
+}
+
+fn describe_compute_budget(data: &[u8]) -> String {
+    let u32_at = |at: usize| data.get(at..at + 4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()));
+    let u64_at = |at: usize| data.get(at..at + 8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()));
+    match data.first() {
+        Some(0) => match (u32_at(1), u32_at(5)) {
+            (Some(units), Some(fee)) => {
+                format!("request {} compute units with an additional fee of {} lamports", units, fee)
+            }
+            _ => format!("undecodable instruction ({} bytes)", data.len()),
+        },
+        Some(1) => u32_at(1).map_or_else(
+            || format!("undecodable instruction ({} bytes)", data.len()),
+            |bytes| format!("request a heap frame of {} bytes", bytes),
+        ),
+        Some(2) => u32_at(1).map_or_else(
+            || format!("undecodable instruction ({} bytes)", data.len()),
+            |units| format!("set the compute unit limit to {}", units),
+        ),
+        Some(3) => u64_at(1).map_or_else(
+            || format!("undecodable instruction ({} bytes)", data.len()),
+            |price| format!("set the compute unit price to {} micro-lamports", price),
+        ),
+        Some(tag) => format!("instruction {} ({} bytes)", tag, data.len()),
+        None => "empty instruction".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use solana_sdk::hash::Hash;
+    use solana_sdk::instruction::{AccountMeta, Instruction};
+    use solana_sdk::message::Message;
+    use solana_sdk::native_token::LAMPORTS_PER_SOL;
+    use solana_sdk::pubkey::Pubkey;
+
+    use crate::create_unsigned_transaction;
+    use crate::review::{fingerprint, Review, COMPUTE_BUDGET_PROGRAM_ID, TOKEN_PROGRAM_ID};
+
+    #[test]
+    fn test_review() {
+        let payer = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        let mut tx = create_unsigned_transaction(LAMPORTS_PER_SOL / 2, &to, Some("rent".to_string()), &payer);
+        tx.message.recent_blockhash = Hash::new_unique();
+
+        let review = Review(&tx.message).to_string();
+        assert!(review.contains(&format!("Fee payer: {}", payer)));
+        assert!(review.contains(&format!("Recent block hash: {}", tx.message.recent_blockhash)));
+        assert!(review.contains(&format!("System: transfer 0.5 SOL from {} to {}", payer, to)));
+        assert!(review.contains("Memo: \"rent\""));
+        assert!(review.contains(&fingerprint(&tx.message)));
+
+        // Any change to the message has to change the fingerprint.
+        let mut other = tx.message.clone();
+        other.recent_blockhash = Hash::new_unique();
+        assert_ne!(fingerprint(&tx.message), fingerprint(&other));
+    }
+
+    #[test]
+    fn test_review_token_and_compute_budget() {
+        let payer = Pubkey::new_unique();
+        let (source, destination) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut transfer = vec![3];
+        transfer.extend_from_slice(&42u64.to_le_bytes());
+        let mut price = vec![3];
+        price.extend_from_slice(&1000u64.to_le_bytes());
+        let instructions = [
+            Instruction::new_with_bytes(Pubkey::from_str(COMPUTE_BUDGET_PROGRAM_ID).unwrap(), &price, vec![]),
+            Instruction::new_with_bytes(
+                Pubkey::from_str(TOKEN_PROGRAM_ID).unwrap(),
+                &transfer,
+                vec![
+                    AccountMeta::new(source, false),
+                    AccountMeta::new(destination, false),
+                    AccountMeta::new_readonly(payer, true),
+                ],
+            ),
+        ];
+        let message = Message::new(&instructions, Some(&payer));
+
+        let review = Review(&message).to_string();
+        assert!(review.contains("Compute budget: set the compute unit price to 1000 micro-lamports"));
+        assert!(review.contains(&format!("Token: transfer 42 base units from {} to {}", source, destination)));
+    }
+}
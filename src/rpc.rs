@@ -0,0 +1,121 @@
+use std::fmt::Display;
+use std::time::Duration;
+
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::Error;
+
+/// A list of RPC endpoints of the same cluster, tried in order.
+pub struct Rpc {
+    clients: Vec<RpcClient>,
+    quorum: usize,
+}
+
+impl Rpc {
+    /// `quorum` is how many endpoints must agree on critical reads, 1 means the first one that answers.
+    pub fn new(urls: &[String], timeout: Duration, commitment: CommitmentConfig, quorum: usize) -> Result<Self, Error> {
+        if urls.is_empty() || quorum == 0 || quorum > urls.len() {
+            return Err(Error::InvalidQuorum { quorum, endpoints: urls.len() });
+        }
+        let clients = urls
+            .iter()
+            .map(|url| RpcClient::new_with_timeout_and_commitment(url.clone(), timeout, commitment))
+            .collect();
+        Ok(Self { clients, quorum })
+    }
+
+    /// Call `f` on each endpoint in turn until one succeeds, errors and timeouts move on to the next one.
+    pub fn failover<T, E: Display>(&self, f: impl Fn(&RpcClient) -> Result<T, E>) -> Result<T, E> {
+        let (last, rest) = self.clients.split_last().expect("there is at least one endpoint");
+        for client in rest {
+            match f(client) {
+                Ok(t) => return Ok(t),
+                Err(e) => eprintln!("{} failed, trying the next endpoint: {}", client.url(), e),
+            }
+        }
+        f(last)
+    }
+
+    /// The balance, as long as a quorum of endpoints reports the same one.
+    pub fn get_balance(&self, address: &Pubkey) -> Result<u64, Error> {
+        if self.quorum == 1 {
+            return self.failover(|client| client.get_balance(address)).map_err(Error::BalaceFailed);
+        }
+        let balances: Vec<_> = self.clients.iter().filter_map(|client| client.get_balance(address).ok()).collect();
+        let best = balances.iter().max_by_key(|&b| balances.iter().filter(|&other| other == b).count());
+        let agreeing = best.map_or(0, |b| balances.iter().filter(|&other| other == b).count());
+        match best {
+            Some(&balance) if agreeing >= self.quorum => Ok(balance),
+            _ => Err(Error::NoQuorum { needed: self.quorum, agreeing }),
+        }
+    }
+
+    /// A recent block hash that a quorum of endpoints considers valid.
+    /// Endpoints are rarely on the exact same slot, so they don't have to agree on which hash is the latest.
+    /// The quorum read fetches and checks the hash at the finalized commitment, so the endpoints agree on the same
+    /// thing, and any endpoint the transaction is later submitted to knows it.
+    pub fn get_latest_blockhash(&self) -> Result<Hash, Error> {
+        if self.quorum == 1 {
+            return self.failover(RpcClient::get_latest_blockhash).map_err(Error::RecentHashFailed);
+        }
+        let commitment = CommitmentConfig::finalized();
+        let (hash, _) = self
+            .failover(|client| client.get_latest_blockhash_with_commitment(commitment))
+            .map_err(Error::RecentHashFailed)?;
+        let agreeing =
+            self.clients.iter().filter(|client| client.is_blockhash_valid(&hash, commitment).unwrap_or(false)).count();
+        if agreeing < self.quorum {
+            return Err(Error::NoQuorum { needed: self.quorum, agreeing });
+        }
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use solana_sdk::commitment_config::CommitmentConfig;
+    use solana_sdk::pubkey::Pubkey;
+    use solana_streamer::socket::SocketAddrSpace;
+    use solana_test_validator::TestValidator;
+
+    use crate::rpc::Rpc;
+    use crate::Error;
+
+    // Nothing listens on port 1, so requests fail right away.
+    const DEAD: &str = "http://127.0.0.1:1";
+
+    fn rpc(urls: &[&str], quorum: usize) -> Result<Rpc, Error> {
+        let urls: Vec<_> = urls.iter().map(|url| url.to_string()).collect();
+        Rpc::new(&urls, Duration::from_secs(10), CommitmentConfig::processed(), quorum)
+    }
+
+    #[test]
+    fn test_failover_and_quorum() {
+        let payer = Pubkey::new_unique();
+        let testnet = TestValidator::with_no_fees(payer, None, SocketAddrSpace::Unspecified);
+        let live = testnet.rpc_url();
+        let balance = testnet.get_rpc_client().get_balance(&payer).unwrap();
+
+        let failover = rpc(&[DEAD, &live], 1).unwrap();
+        assert_eq!(failover.get_balance(&payer).unwrap(), balance);
+        failover.get_latest_blockhash().unwrap();
+
+        let quorum = rpc(&[&live, DEAD, &live], 2).unwrap();
+        assert_eq!(quorum.get_balance(&payer).unwrap(), balance);
+        quorum.get_latest_blockhash().unwrap();
+
+        let no_quorum = rpc(&[&live, DEAD], 2).unwrap();
+        assert!(matches!(no_quorum.get_balance(&payer), Err(Error::NoQuorum { needed: 2, agreeing: 1 })));
+        assert!(matches!(no_quorum.get_latest_blockhash(), Err(Error::NoQuorum { needed: 2, agreeing: 1 })));
+
+        assert!(matches!(rpc(&[DEAD], 1).unwrap().get_balance(&payer), Err(Error::BalaceFailed(_))));
+        assert!(matches!(rpc(&[&live], 2), Err(Error::InvalidQuorum { quorum: 2, endpoints: 1 })));
+        assert!(matches!(rpc(&[], 1), Err(Error::InvalidQuorum { .. })));
+        assert!(matches!(rpc(&[&live], 0), Err(Error::InvalidQuorum { .. })));
+    }
+}
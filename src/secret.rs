@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Formatter};
+use std::mem::{self, ManuallyDrop, MaybeUninit};
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{self, Ordering};
+use std::sync::Mutex;
+
+/// How many live secrets lie on every locked page, by page address.
+/// Secrets share pages, so a page is only unlocked once the last one on it is gone.
+#[cfg(unix)]
+static LOCKED_PAGES: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+/// Holds secret material on the heap, locked into memory where the platform supports it,
+/// and wipes its bytes when dropped. It never prints its content in `Debug`.
+pub struct Secret<T> {
+    inner: Box<ManuallyDrop<T>>,
+}
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self::from_slot(&mut MaybeUninit::new(value))
+    }
+
+    /// Build the value with `init`, and wipe the stack slot it was returned in once it's moved to the heap.
+    pub fn from_fn(init: impl FnOnce() -> T) -> Self {
+        Self::from_slot(&mut MaybeUninit::new(init()))
+    }
+
+    /// Move the value out of `slot` without leaving a copy behind, `slot` is wiped and must not be read again.
+    fn from_slot(slot: &mut MaybeUninit<T>) -> Self {
+        let mut inner = Box::new(MaybeUninit::<ManuallyDrop<T>>::uninit());
+        // Safety: `slot` is initialized, the value is copied into the box and the copy in `slot` is forgotten.
+        // `ManuallyDrop<T>` has the same layout as `T`, so the initialized box can be cast to it.
+        let inner = unsafe {
+            ptr::copy_nonoverlapping(slot.as_ptr(), inner.as_mut_ptr() as *mut T, 1);
+            wipe_bytes(slot.as_mut_ptr() as *mut u8, mem::size_of::<T>());
+            Box::from_raw(Box::into_raw(inner) as *mut ManuallyDrop<T>)
+        };
+        lock(&*inner as *const ManuallyDrop<T> as *const u8, mem::size_of::<T>());
+        Self { inner }
+    }
+
+    /// Drop the value and wipe its bytes, the box still owns them afterwards.
+    ///
+    /// # Safety
+    /// Must be called exactly once, the value must not be accessed afterwards.
+    unsafe fn clear(&mut self) {
+        let ptr = &mut *self.inner as *mut ManuallyDrop<T> as *mut u8;
+        ManuallyDrop::drop(&mut self.inner);
+        wipe_bytes(ptr, mem::size_of::<T>());
+        unlock(ptr, mem::size_of::<T>());
+    }
+}
+
+impl<T> Deref for Secret<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for Secret<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T> Drop for Secret<T> {
+    fn drop(&mut self) {
+        // Safety: this is the only place it's called, and the box is deallocated right after.
+        unsafe { self.clear() };
+    }
+}
+
+impl<T> Debug for Secret<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+/// Overwrite `dst` with `value` in a way the compiler can't optimize out.
+/// The previous value is *not* dropped, so this should only be used with types that don't own heap memory.
+pub fn overwrite<T>(dst: &mut T, value: T) {
+    // Safety: `dst` is a valid and aligned mutable reference.
+    unsafe { ptr::write_volatile(dst, value) };
+    atomic::compiler_fence(Ordering::SeqCst);
+}
+
+/// # Safety
+/// `ptr` must be valid for writes of `len` bytes.
+unsafe fn wipe_bytes(ptr: *mut u8, len: usize) {
+    for i in 0..len {
+        ptr::write_volatile(ptr.add(i), 0);
+    }
+    atomic::compiler_fence(Ordering::SeqCst);
+}
+
+/// The addresses of the pages `len` bytes at `ptr` lie on.
+#[cfg(unix)]
+fn pages(ptr: *const u8, len: usize, page_size: usize) -> impl Iterator<Item = usize> {
+    let start = ptr as usize / page_size * page_size;
+    let end = if len == 0 { start } else { ptr as usize + len };
+    (start..end).step_by(page_size)
+}
+
+#[cfg(unix)]
+fn page_size() -> usize {
+    // Safety: `sysconf` has no preconditions.
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
+
+#[cfg(unix)]
+fn lock(ptr: *const u8, len: usize) {
+    let mut locked = LOCKED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    let page_size = page_size();
+    for page in pages(ptr, len, page_size) {
+        let count = locked.entry(page).or_insert(0);
+        if *count == 0 {
+            // This is best effort, it can fail if we're over `RLIMIT_MEMLOCK`.
+            unsafe { libc::mlock(page as *const libc::c_void, page_size) };
+        }
+        *count += 1;
+    }
+}
+
+#[cfg(unix)]
+fn unlock(ptr: *const u8, len: usize) {
+    let mut locked = LOCKED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    let page_size = page_size();
+    for page in pages(ptr, len, page_size) {
+        if let Some(count) = locked.get_mut(&page) {
+            *count -= 1;
+            if *count == 0 {
+                locked.remove(&page);
+                unsafe { libc::munlock(page as *const libc::c_void, page_size) };
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn lock(_: *const u8, _: usize) {}
+
+#[cfg(not(unix))]
+fn unlock(_: *const u8, _: usize) {}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::ManuallyDrop;
+
+    use curv::elliptic::curves::{Ed25519, Scalar};
+
+    use crate::secret::{overwrite, wipe_bytes, Secret};
+    #[cfg(unix)]
+    use crate::secret::{page_size, pages, LOCKED_PAGES};
+
+    #[test]
+    fn test_wipe_bytes() {
+        let mut buf = [0xAAu8; 100];
+        unsafe { wipe_bytes(buf.as_mut_ptr(), buf.len()) };
+        assert_eq!(buf, [0u8; 100]);
+    }
+
+    #[test]
+    fn test_overwrite_scalar() {
+        let mut scalar = Scalar::<Ed25519>::random();
+        overwrite(&mut scalar, Scalar::zero());
+        assert_eq!(scalar, Scalar::zero());
+    }
+
+    #[test]
+    fn test_secret_redacted() {
+        let mut secret = Secret::new([42u8; 32]);
+        assert_eq!(*secret, [42u8; 32]);
+        secret[0] = 1;
+        assert_eq!(secret[0], 1);
+        assert_eq!(format!("{:?}", secret), "Secret(<redacted>)");
+    }
+
+    #[test]
+    fn test_secret_wiped_on_drop() {
+        // Only clear it, so the bytes are still allocated when we look at them, the box is leaked.
+        let mut secret = ManuallyDrop::new(Secret::from_fn(|| [42u8; 32]));
+        let bytes = &**secret as *const [u8; 32];
+        unsafe {
+            secret.clear();
+            assert_eq!(*bytes, [0u8; 32]);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shared_pages_stay_locked() {
+        assert_eq!(pages(4000 as *const u8, 200, 4096).collect::<Vec<_>>(), vec![0, 4096]);
+        assert_eq!(pages(4096 as *const u8, 4096, 4096).collect::<Vec<_>>(), vec![4096]);
+
+        // Small secrets usually share a page, dropping one mustn't unlock the other.
+        let (first, second) = (Secret::new([1u8; 32]), Secret::new([2u8; 32]));
+        let page = pages(second.as_ptr(), 32, page_size()).next().unwrap();
+        drop(first);
+        assert!(LOCKED_PAGES.lock().unwrap().get(&page).copied().unwrap_or(0) >= 1);
+    }
+}
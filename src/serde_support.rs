@@ -0,0 +1,258 @@
+//! `serde` support for the protocol messages and session types.
+//!
+//! Human readable formats (JSON, YAML...) get the same named fields as `Encoding::Json`,
+//! binary formats (CBOR, bincode...) get the compact tagged encoding from `Serialize::serialize`.
+
+use std::fmt::{self, Display};
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::ser::{SerializeSeq, Serializer};
+
+use crate::serialization::{AggMessage1, PartialSignature, SecretAggStepOne, Serialize};
+
+macro_rules! impl_serde {
+    ($($ty:ty),+) => {$(
+        impl serde::Serialize for $ty {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                if serializer.is_human_readable() {
+                    serde::Serialize::serialize(&Serialize::to_json(self), serializer)
+                } else {
+                    let mut bytes = Vec::with_capacity(self.size_hint());
+                    Serialize::serialize(self, &mut bytes);
+                    serializer.serialize_bytes(&bytes)
+                }
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                if deserializer.is_human_readable() {
+                    let value = <serde_json::Value as serde::Deserialize>::deserialize(deserializer)?;
+                    <$ty as Serialize>::from_json(&value).map_err(de::Error::custom)
+                } else {
+                    let bytes = deserializer.deserialize_bytes(BytesVisitor)?;
+                    <$ty as Serialize>::deserialize(&bytes).map_err(de::Error::custom)
+                }
+            }
+        }
+    )+};
+}
+
+impl_serde!(AggMessage1, PartialSignature, SecretAggStepOne);
+
+/// Accepts both native byte strings and sequences of bytes, as not all binary formats have the former.
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a byte array")
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(v)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut out = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(b) = seq.next_element()? {
+            out.push(b);
+        }
+        Ok(out)
+    }
+}
+
+/// Use the `Display`/`FromStr` representation in human readable formats (base58 for keys and hashes),
+/// and fall back to the type's own `serde` implementation in binary formats.
+pub mod display_fromstr {
+    use super::*;
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Display + serde::Serialize,
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.collect_str(value)
+        } else {
+            serde::Serialize::serialize(value, serializer)
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr + serde::Deserialize<'de>,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+            s.parse().map_err(de::Error::custom)
+        } else {
+            <T as serde::Deserialize>::deserialize(deserializer)
+        }
+    }
+}
+
+/// A single value in its [`display_fromstr`] representation.
+struct Item<T>(T);
+
+impl<T: Display + serde::Serialize> serde::Serialize for Item<&T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        display_fromstr::serialize(self.0, serializer)
+    }
+}
+
+impl<'de, T> serde::Deserialize<'de> for Item<T>
+where
+    T: FromStr + serde::Deserialize<'de>,
+    T::Err: Display,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        display_fromstr::deserialize(deserializer).map(Item)
+    }
+}
+
+/// Same as [`display_fromstr`] but for an optional value.
+pub mod display_fromstr_option {
+    use super::*;
+
+    pub fn serialize<T, S>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Display + serde::Serialize,
+        S: Serializer,
+    {
+        match value {
+            Some(value) => serializer.serialize_some(&Item(value)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: FromStr + serde::Deserialize<'de>,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        Ok(<Option<Item<T>> as serde::Deserialize>::deserialize(deserializer)?.map(|Item(value)| value))
+    }
+}
+
+/// Same as [`display_fromstr`] but for a list of values.
+pub mod display_fromstr_vec {
+    use super::*;
+
+    #[allow(clippy::ptr_arg)]
+    pub fn serialize<T, S>(values: &Vec<T>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Display + serde::Serialize,
+        S: Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(values.len()))?;
+        for value in values {
+            seq.serialize_element(&Item(value))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
+    where
+        T: FromStr + serde::Deserialize<'de>,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        struct VecVisitor<T>(PhantomData<T>);
+
+        impl<'de, T> Visitor<'de> for VecVisitor<T>
+        where
+            T: FromStr + serde::Deserialize<'de>,
+            T::Err: Display,
+        {
+            type Value = Vec<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a list")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut out = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(Item(value)) = seq.next_element()? {
+                    out.push(value);
+                }
+                Ok(out)
+            }
+        }
+
+        deserializer.deserialize_seq(VecVisitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use multi_party_eddsa::protocols::{musig2, ExpandedKeyPair};
+    use solana_sdk::hash::Hash;
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::signature::Signature;
+
+    use crate::batch::Payment;
+    use crate::serialization::{AggMessage1, PartialSignature, SecretAggStepOne};
+    use crate::tss::{AggregateKey, SessionParams};
+
+    fn roundtrip<T>(t: &T)
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned + PartialEq + Debug,
+    {
+        let json = serde_json::to_string(t).unwrap();
+        assert_eq!(&serde_json::from_str::<T>(&json).unwrap(), t);
+        let binary = bincode::serialize(t).unwrap();
+        assert_eq!(&bincode::deserialize::<T>(&binary).unwrap(), t);
+    }
+
+    #[test]
+    fn test_serde_messages() {
+        let mut data = [0u8; 32];
+        for i in 0..u8::MAX {
+            data.fill(i);
+            let (private_nonces, public_nonces) =
+                musig2::generate_partial_nonces(&ExpandedKeyPair::create(), Some(&data));
+            roundtrip(&AggMessage1 { public_nonces: public_nonces.clone(), sender: Pubkey::new(&data) });
+            roundtrip(&SecretAggStepOne { private_nonces, public_nonces });
+            roundtrip(&PartialSignature(Signature::new(&[i; 64])));
+        }
+    }
+
+    #[test]
+    fn test_serde_session() {
+        let keys: Vec<_> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        let session = SessionParams {
+            keys: keys.clone(),
+            payments: vec![
+                Payment { to: Pubkey::new_unique(), amount: 500_000_000 },
+                Payment { to: Pubkey::new_unique(), amount: 1 },
+            ],
+            memo: Some("serde".to_string()),
+            recent_block_hash: Hash::new_unique(),
+            label: Some("customer-1".to_string()),
+            fee_payer: Some(Pubkey::new_unique()),
+        };
+        roundtrip(&session);
+        roundtrip(&SessionParams { memo: None, label: None, fee_payer: None, ..session.clone() });
+
+        let json = serde_json::to_value(&session).unwrap();
+        assert_eq!(json["payments"][0]["to"], session.payments[0].to.to_string());
+        assert_eq!(json["keys"][1], keys[1].to_string());
+        assert_eq!(json["recent_block_hash"], session.recent_block_hash.to_string());
+        assert_eq!(json["fee_payer"], session.fee_payer.unwrap().to_string());
+
+        roundtrip(&AggregateKey { keys, aggregate: Pubkey::new_unique() });
+    }
+}
@@ -1,17 +1,33 @@
 use std::fmt::{Display, Formatter};
+use std::mem;
+use std::str::FromStr;
 
-use curv::elliptic::curves::{DeserializationError, Point, PointFromBytesError, Scalar};
+use curv::elliptic::curves::{DeserializationError, Ed25519, Point, PointFromBytesError, Scalar};
 use multi_party_eddsa::protocols::musig2::{PrivatePartialNonces, PublicPartialNonces};
+use serde_json::{json, Value};
 use solana_sdk::signature::Signature;
 use spl_memo::solana_program::pubkey::Pubkey;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::secret;
+
+/// Fits the JSON form of every message.
+const JSON_CAPACITY: usize = 1024;
 
 #[derive(Debug)]
 pub enum Error {
     InputTooShort { expected: usize, found: usize },
     BadBase58(bs58::decode::Error),
+    BadBase64(base64::DecodeError),
+    BadHex(hex::FromHexError),
+    BadJson(serde_json::Error),
+    InvalidJsonField(&'static str),
+    UnrecognizedEncoding,
     InvalidPoint(PointFromBytesError),
     InvalidScalar(DeserializationError),
     WrongTag { expected: Tag, found: Tag },
+    TrailingBytes { expected: usize, found: usize },
+    AtOffset { offset: usize, error: Box<Error> },
 }
 
 // TODO: Also add a magic tag that is equal to all messages.
@@ -37,6 +53,27 @@ impl From<u8> for Tag {
     }
 }
 
+impl Tag {
+    /// The name used for the `type` field of the JSON encoding.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tag::AggMessage1 => "agg_message1",
+            Tag::AggMessage2 => "agg_message2",
+            Tag::PartialSignature => "partial_signature",
+            Tag::SecretAggStepOne => "secret_agg_step_one",
+            Tag::Unknown => "unknown",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Self {
+        [Tag::AggMessage1, Tag::AggMessage2, Tag::PartialSignature, Tag::SecretAggStepOne]
+            .iter()
+            .copied()
+            .find(|tag| tag.name() == name)
+            .unwrap_or(Tag::Unknown)
+    }
+}
+
 impl Display for Tag {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -56,11 +93,20 @@ impl Display for Error {
                 write!(f, "Input too short, expected: {}, found: {}", expected, found)
             }
             Self::BadBase58(e) => write!(f, "Invalid base58: {}", e),
+            Self::BadBase64(e) => write!(f, "Invalid base64: {}", e),
+            Self::BadHex(e) => write!(f, "Invalid hex: {}", e),
+            Self::BadJson(e) => write!(f, "Invalid JSON: {}", e),
+            Self::InvalidJsonField(field) => write!(f, "Missing or invalid JSON field: {}", field),
+            Self::UnrecognizedEncoding => f.write_str("Input isn't valid base58, base64, hex or JSON"),
             Self::InvalidPoint(e) => write!(f, "Invalid Ed25519 Point: {}", e),
             Self::InvalidScalar(e) => write!(f, "Invalid Ed25519 Scalar: {}", e),
             Self::WrongTag { expected, found } => {
                 write!(f, "Expected to find message: {}, instead found: {}", expected, found)
             }
+            Self::TrailingBytes { expected, found } => {
+                write!(f, "Unexpected trailing bytes, expected: {}, found: {}", expected, found)
+            }
+            Self::AtOffset { offset, error } => write!(f, "{} (at byte {})", error, offset),
         }
     }
 }
@@ -87,22 +133,234 @@ impl<T: Serialize> FieldError<T> for Result<T, Error> {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Base58,
+    Base64,
+    Hex,
+    Json,
+}
+
+impl FromStr for Encoding {
+    type Err = crate::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "base58" | "bs58" => Ok(Self::Base58),
+            "base64" => Ok(Self::Base64),
+            "hex" => Ok(Self::Hex),
+            "json" => Ok(Self::Json),
+            _ => Err(crate::Error::WrongEncoding(s.to_string())),
+        }
+    }
+}
+
 pub trait Serialize: Sized {
     fn serialize_bs58(&self) -> String {
-        let mut vec = Vec::with_capacity(self.size_hint());
+        let mut vec = Zeroizing::new(Vec::with_capacity(self.size_hint()));
         self.serialize(&mut vec);
-        bs58::encode(vec).into_string()
+        bs58::encode(&*vec).into_string()
+    }
+    fn encode(&self, encoding: Encoding) -> String {
+        let binary = || {
+            let mut vec = Zeroizing::new(Vec::with_capacity(self.size_hint()));
+            self.serialize(&mut vec);
+            vec
+        };
+        match encoding {
+            Encoding::Base58 => bs58::encode(&*binary()).into_string(),
+            Encoding::Base64 => base64::encode(&*binary()),
+            Encoding::Hex => hex::encode(&*binary()),
+            Encoding::Json => {
+                let mut value = self.to_json();
+                // Big enough that the buffer never moves, which would leave a copy behind.
+                let mut out = Zeroizing::new(Vec::with_capacity(JSON_CAPACITY));
+                serde_json::to_writer(&mut *out, &value).expect("a JSON value can be serialized");
+                zeroize_json(&mut value);
+                String::from_utf8(mem::take(&mut *out)).expect("JSON is valid UTF-8")
+            }
+        }
     }
+    fn decode_with(s: &str, encoding: Encoding) -> Result<Self, Error> {
+        match encoding {
+            Encoding::Base58 => Self::deserialize_bs58(s),
+            Encoding::Base64 => Self::deserialize(&Zeroizing::new(base64::decode(s).map_err(Error::BadBase64)?)),
+            Encoding::Hex => Self::deserialize(&Zeroizing::new(hex::decode(s).map_err(Error::BadHex)?)),
+            Encoding::Json => {
+                let mut value = serde_json::from_str(s).map_err(Error::BadJson)?;
+                let res = Self::from_json(&value);
+                zeroize_json(&mut value);
+                res
+            }
+        }
+    }
+    /// Decode a message without knowing its encoding.
+    /// JSON is recognized by its opening brace, otherwise we try hex, base58 and base64 in that order,
+    /// and take the first one that decodes into a valid message.
+    fn decode(s: &str) -> Result<Self, Error> {
+        let s = s.trim();
+        if s.starts_with('{') {
+            return Self::decode_with(s, Encoding::Json);
+        }
+        let candidates = [hex::decode(s).ok(), bs58::decode(s).into_vec().ok(), base64::decode(s).ok()];
+        let mut first_error = None;
+        for bytes in candidates.iter().flatten() {
+            match Self::deserialize(bytes) {
+                Ok(res) => return Ok(res),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        Err(first_error.unwrap_or(Error::UnrecognizedEncoding))
+    }
+    fn to_json(&self) -> Value;
+    fn from_json(value: &Value) -> Result<Self, Error>;
     fn serialize(&self, append_to: &mut Vec<u8>);
     fn deserialize_bs58(s: impl AsRef<[u8]>) -> Result<Self, Error> {
-        let out = bs58::decode(s).into_vec().map_err(Error::BadBase58)?;
+        let out = Zeroizing::new(bs58::decode(s).into_vec().map_err(Error::BadBase58)?);
         Self::deserialize(&out)
     }
-    // TODO: Use a cursor instead, more fail safe.
-    fn deserialize(b: &[u8]) -> Result<Self, Error>;
+    /// Decode a whole message, the input must contain exactly one message and nothing else.
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader::new(b);
+        let res = Self::read(&mut reader)?;
+        reader.finish()?;
+        Ok(res)
+    }
+    /// Decode a message from the current position of the reader.
+    fn read(reader: &mut Reader<'_>) -> Result<Self, Error>;
     fn size_hint(&self) -> usize;
 }
 
+/// A cursor over an input buffer, every read is bounds checked and errors carry the offset they happened at.
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, offset: 0 }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.offset
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self.offset.saturating_add(len);
+        if end > self.buf.len() {
+            return Err(Error::InputTooShort { expected: end, found: self.buf.len() });
+        }
+        let bytes = &self.buf[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.read_bytes(N)?);
+        Ok(out)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    /// Read the message tag and make sure it's the one we expected.
+    pub fn read_tag(&mut self, expected: Tag) -> Result<(), Error> {
+        let offset = self.offset;
+        let found = Tag::from(self.read_u8()?);
+        if found != expected {
+            return Err(Error::AtOffset { offset, error: Box::new(Error::WrongTag { expected, found }) });
+        }
+        Ok(())
+    }
+
+    pub fn read_point(&mut self) -> Result<Point<Ed25519>, Error> {
+        let offset = self.offset;
+        let bytes = self.read_array::<32>()?;
+        Point::from_bytes(&bytes).map_err(|e| Error::AtOffset { offset, error: Box::new(e.into()) })
+    }
+
+    pub fn read_scalar(&mut self) -> Result<Scalar<Ed25519>, Error> {
+        let offset = self.offset;
+        let bytes = self.read_array::<32>()?;
+        Scalar::from_bytes(&bytes).map_err(|e| Error::AtOffset { offset, error: Box::new(e.into()) })
+    }
+
+    pub fn read_pubkey(&mut self) -> Result<Pubkey, Error> {
+        Ok(Pubkey::new_from_array(self.read_array()?))
+    }
+
+    pub fn read_signature(&mut self) -> Result<Signature, Error> {
+        Ok(Signature::new(self.read_bytes(64)?))
+    }
+
+    /// Make sure the whole input was consumed.
+    pub fn finish(self) -> Result<(), Error> {
+        if self.offset != self.buf.len() {
+            return Err(Error::TrailingBytes { expected: self.offset, found: self.buf.len() });
+        }
+        Ok(())
+    }
+}
+
+fn json_field<'a>(value: &'a Value, field: &'static str) -> Result<&'a Value, Error> {
+    value.get(field).ok_or(Error::InvalidJsonField(field))
+}
+
+/// Wipe every string of a JSON value, e.g. the hex encoded private nonces.
+fn zeroize_json(value: &mut Value) {
+    match value {
+        Value::String(s) => s.zeroize(),
+        Value::Array(items) => items.iter_mut().for_each(zeroize_json),
+        Value::Object(fields) => fields.values_mut().for_each(zeroize_json),
+        _ => {}
+    }
+}
+
+fn json_str<'a>(value: &'a Value, field: &'static str) -> Result<&'a str, Error> {
+    json_field(value, field)?.as_str().ok_or(Error::InvalidJsonField(field))
+}
+
+fn json_check_type(value: &Value, expected: Tag) -> Result<(), Error> {
+    let found = Tag::from_name(json_str(value, "type")?);
+    if found != expected {
+        return Err(Error::WrongTag { expected, found });
+    }
+    Ok(())
+}
+
+/// Read a JSON array of hex strings, each holding exactly `N` bytes.
+fn json_hex_pair<const N: usize>(value: &Value, field: &'static str) -> Result<[[u8; N]; 2], Error> {
+    let array = json_field(value, field)?.as_array().filter(|a| a.len() == 2).ok_or(Error::InvalidJsonField(field))?;
+    let mut out = [[0u8; N]; 2];
+    for (out, item) in out.iter_mut().zip(array) {
+        let bytes =
+            Zeroizing::new(hex::decode(item.as_str().ok_or(Error::InvalidJsonField(field))?).map_err(Error::BadHex)?);
+        if bytes.len() != N {
+            return Err(Error::InvalidJsonField(field));
+        }
+        out.copy_from_slice(&bytes);
+    }
+    Ok(out)
+}
+
+fn json_bs58<const N: usize>(value: &Value, field: &'static str) -> Result<[u8; N], Error> {
+    let bytes = bs58::decode(json_str(value, field)?).into_vec().map_err(Error::BadBase58)?;
+    let mut out = [0u8; N];
+    if bytes.len() != N {
+        return Err(Error::InvalidJsonField(field));
+    }
+    out.copy_from_slice(&bytes);
+    Ok(out)
+}
+
 #[derive(Debug, PartialEq)]
 pub struct AggMessage1 {
     pub public_nonces: PublicPartialNonces,
@@ -117,17 +375,27 @@ impl Serialize for AggMessage1 {
         append_to.extend(&*self.public_nonces.R[1].to_bytes(true));
         append_to.extend(self.sender.to_bytes());
     }
-    fn deserialize(b: &[u8]) -> Result<Self, Error> {
-        if b.len() < 1 + 32 + 32 + 32 {
-            return Err(Error::InputTooShort { expected: 1 + 32 + 32 + 32, found: b.len() });
-        }
-        let tag = Tag::from(b[0]);
-        if tag != Tag::AggMessage1 {
-            return Err(Error::WrongTag { expected: Tag::AggMessage1, found: tag });
-        }
-        let public_nonces =
-            PublicPartialNonces { R: [Point::from_bytes(&b[1..32 + 1])?, Point::from_bytes(&b[1 + 32..64 + 1])?] };
-        let sender = Pubkey::new(&b[64 + 1..64 + 32 + 1]);
+    fn read(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        reader.read_tag(Tag::AggMessage1)?;
+        let public_nonces = PublicPartialNonces { R: [reader.read_point()?, reader.read_point()?] };
+        let sender = reader.read_pubkey()?;
+        Ok(Self { public_nonces, sender })
+    }
+    fn to_json(&self) -> Value {
+        json!({
+            "type": Tag::AggMessage1.name(),
+            "sender": self.sender.to_string(),
+            "public_nonces": [
+                hex::encode(&*self.public_nonces.R[0].to_bytes(true)),
+                hex::encode(&*self.public_nonces.R[1].to_bytes(true)),
+            ],
+        })
+    }
+    fn from_json(value: &Value) -> Result<Self, Error> {
+        json_check_type(value, Tag::AggMessage1)?;
+        let [nonce0, nonce1] = json_hex_pair::<32>(value, "public_nonces")?;
+        let public_nonces = PublicPartialNonces { R: [Point::from_bytes(&nonce0)?, Point::from_bytes(&nonce1)?] };
+        let sender = Pubkey::new_from_array(json_bs58(value, "sender")?);
         Ok(Self { public_nonces, sender })
     }
     fn size_hint(&self) -> usize {
@@ -145,27 +413,59 @@ impl Serialize for PartialSignature {
 
         append_to.extend(self.0.as_ref());
     }
-    fn deserialize(b: &[u8]) -> Result<Self, Error> {
-        if b.len() < 1 + 64 {
-            return Err(Error::InputTooShort { expected: 1 + 64, found: b.len() });
-        }
-        let tag = Tag::from(b[0]);
-        if tag != Tag::PartialSignature {
-            return Err(Error::WrongTag { expected: Tag::PartialSignature, found: tag });
-        }
-        Ok(PartialSignature(Signature::new(&b[1..1 + 64])))
+    fn read(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        reader.read_tag(Tag::PartialSignature)?;
+        Ok(PartialSignature(reader.read_signature()?))
+    }
+    fn to_json(&self) -> Value {
+        json!({ "type": Tag::PartialSignature.name(), "signature": self.0.to_string() })
+    }
+    fn from_json(value: &Value) -> Result<Self, Error> {
+        json_check_type(value, Tag::PartialSignature)?;
+        Ok(PartialSignature(Signature::new(&json_bs58::<64>(value, "signature")?)))
     }
     fn size_hint(&self) -> usize {
         1 + 64
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// The private nonces are wiped when this is dropped, and are never printed.
+#[derive(PartialEq)]
 pub struct SecretAggStepOne {
     pub private_nonces: PrivatePartialNonces,
     pub public_nonces: PublicPartialNonces,
 }
 
+impl SecretAggStepOne {
+    /// Move the private nonces out for the one signature they're for, and leave zeros behind, instead of a copy.
+    pub fn take_private_nonces(&mut self) -> PrivatePartialNonces {
+        mem::replace(&mut self.private_nonces, PrivatePartialNonces { r: [Scalar::zero(), Scalar::zero()] })
+    }
+}
+
+impl Zeroize for SecretAggStepOne {
+    fn zeroize(&mut self) {
+        for r in &mut self.private_nonces.r {
+            secret::overwrite(r, Scalar::zero());
+        }
+    }
+}
+
+impl Drop for SecretAggStepOne {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl std::fmt::Debug for SecretAggStepOne {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecretAggStepOne")
+            .field("private_nonces", &"<redacted>")
+            .field("public_nonces", &self.public_nonces)
+            .finish()
+    }
+}
+
 impl Serialize for SecretAggStepOne {
     fn serialize(&self, append_to: &mut Vec<u8>) {
         append_to.reserve(self.size_hint());
@@ -176,21 +476,31 @@ impl Serialize for SecretAggStepOne {
         append_to.extend(&*self.public_nonces.R[0].to_bytes(true));
         append_to.extend(&*self.public_nonces.R[1].to_bytes(true));
     }
-    fn deserialize(b: &[u8]) -> Result<Self, Error> {
-        if b.len() < 1 + 64 + 64 {
-            return Err(Error::InputTooShort { expected: 1 + 64 + 64, found: b.len() });
-        }
-
-        let tag = Tag::from(b[0]);
-        if tag != Tag::SecretAggStepOne {
-            return Err(Error::WrongTag { expected: Tag::SecretAggStepOne, found: tag });
-        }
-        let private_nonces =
-            PrivatePartialNonces { r: [Scalar::from_bytes(&b[1..1 + 32])?, Scalar::from_bytes(&b[1 + 32..1 + 64])?] };
-        #[allow(non_snake_case)]
-        let public_nonces = PublicPartialNonces {
-            R: [Point::from_bytes(&b[1 + 64..1 + 64 + 32])?, Point::from_bytes(&b[1 + 96..1 + 96 + 32])?],
-        };
+    fn read(reader: &mut Reader<'_>) -> Result<Self, Error> {
+        reader.read_tag(Tag::SecretAggStepOne)?;
+        let private_nonces = PrivatePartialNonces { r: [reader.read_scalar()?, reader.read_scalar()?] };
+        let public_nonces = PublicPartialNonces { R: [reader.read_point()?, reader.read_point()?] };
+        Ok(Self { private_nonces, public_nonces })
+    }
+    fn to_json(&self) -> Value {
+        json!({
+            "type": Tag::SecretAggStepOne.name(),
+            "private_nonces": [
+                hex::encode(&*self.private_nonces.r[0].to_bytes()),
+                hex::encode(&*self.private_nonces.r[1].to_bytes()),
+            ],
+            "public_nonces": [
+                hex::encode(&*self.public_nonces.R[0].to_bytes(true)),
+                hex::encode(&*self.public_nonces.R[1].to_bytes(true)),
+            ],
+        })
+    }
+    fn from_json(value: &Value) -> Result<Self, Error> {
+        json_check_type(value, Tag::SecretAggStepOne)?;
+        let r = Zeroizing::new(json_hex_pair::<32>(value, "private_nonces")?);
+        let [nonce0, nonce1] = json_hex_pair::<32>(value, "public_nonces")?;
+        let private_nonces = PrivatePartialNonces { r: [Scalar::from_bytes(&r[0])?, Scalar::from_bytes(&r[1])?] };
+        let public_nonces = PublicPartialNonces { R: [Point::from_bytes(&nonce0)?, Point::from_bytes(&nonce1)?] };
         Ok(Self { private_nonces, public_nonces })
     }
     fn size_hint(&self) -> usize {
@@ -201,12 +511,18 @@ impl Serialize for SecretAggStepOne {
 #[cfg(test)]
 mod tests {
     use std::fmt::Debug;
+    use std::mem::{self, MaybeUninit};
+    use std::ptr;
 
     use multi_party_eddsa::protocols::{musig2, ExpandedKeyPair};
     use solana_sdk::signature::Signature;
     use spl_memo::solana_program::pubkey::Pubkey;
 
-    use crate::serialization::{AggMessage1, PartialSignature, SecretAggStepOne, Serialize};
+    use curv::elliptic::curves::{Ed25519, Scalar};
+    use rand07::{Rng, RngCore};
+    use zeroize::Zeroize;
+
+    use crate::serialization::{AggMessage1, Encoding, Error, PartialSignature, SecretAggStepOne, Serialize, Tag};
 
     #[derive(PartialEq, Debug)]
     struct PanicEq<T: PartialEq + Debug>(T);
@@ -255,4 +571,175 @@ mod tests {
             assert_eq!(PanicEq(secret_agg1), PanicEq(deserialized));
         }
     }
+
+    fn decode_all(b: &[u8]) {
+        let _ = AggMessage1::deserialize(b);
+        let _ = PartialSignature::deserialize(b);
+        let _ = SecretAggStepOne::deserialize(b);
+    }
+
+    fn valid_encodings() -> Vec<Vec<u8>> {
+        let (private_nonces, public_nonces) = musig2::generate_partial_nonces(&ExpandedKeyPair::create(), None);
+        let msg1 = AggMessage1 { public_nonces: public_nonces.clone(), sender: Pubkey::new_unique() };
+        let secret = SecretAggStepOne { private_nonces, public_nonces };
+        let partial_sig = PartialSignature(Signature::new(&[7u8; 64]));
+        let mut out = vec![Vec::new(), Vec::new(), Vec::new()];
+        msg1.serialize(&mut out[0]);
+        secret.serialize(&mut out[1]);
+        partial_sig.serialize(&mut out[2]);
+        out
+    }
+
+    #[test]
+    fn test_random_input_never_panics() {
+        let mut rng = rand07::thread_rng();
+        let tags = [Tag::AggMessage1, Tag::AggMessage2, Tag::PartialSignature, Tag::SecretAggStepOne];
+        for len in 0..160 {
+            for _ in 0..64 {
+                let mut input = vec![0u8; len];
+                rng.fill_bytes(&mut input);
+                // Bias the first byte towards valid tags so we get past the tag check.
+                if len > 0 && rng.gen_bool(0.75) {
+                    input[0] = tags[rng.gen_range(0, tags.len())] as u8;
+                }
+                decode_all(&input);
+                let _ = AggMessage1::deserialize_bs58(bs58::encode(&input).into_string());
+                let _ = AggMessage1::deserialize_bs58(&input);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mutated_input_never_panics() {
+        for encoded in valid_encodings() {
+            for bit in 0..encoded.len() * 8 {
+                let mut mutated = encoded.clone();
+                mutated[bit / 8] ^= 1 << (bit % 8);
+                decode_all(&mutated);
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncated_and_extended_input() {
+        for encoded in valid_encodings() {
+            for len in 0..encoded.len() {
+                let res = match Tag::from(encoded[0]) {
+                    Tag::AggMessage1 => AggMessage1::deserialize(&encoded[..len]).map(|_| ()),
+                    Tag::SecretAggStepOne => SecretAggStepOne::deserialize(&encoded[..len]).map(|_| ()),
+                    Tag::PartialSignature => PartialSignature::deserialize(&encoded[..len]).map(|_| ()),
+                    _ => unreachable!(),
+                };
+                match res {
+                    Err(Error::InputTooShort { found, .. }) => assert_eq!(found, len),
+                    other => panic!("expected InputTooShort for length {}, got: {:?}", len, other),
+                }
+            }
+            let mut extended = encoded.clone();
+            extended.push(0);
+            let res = match Tag::from(encoded[0]) {
+                Tag::AggMessage1 => AggMessage1::deserialize(&extended).map(|_| ()),
+                Tag::SecretAggStepOne => SecretAggStepOne::deserialize(&extended).map(|_| ()),
+                Tag::PartialSignature => PartialSignature::deserialize(&extended).map(|_| ()),
+                _ => unreachable!(),
+            };
+            match res {
+                Err(Error::TrailingBytes { expected, found }) => {
+                    assert_eq!(expected, encoded.len());
+                    assert_eq!(found, encoded.len() + 1);
+                }
+                other => panic!("expected TrailingBytes, got: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_error_offsets() {
+        let encoded = &valid_encodings()[0];
+        let mut wrong_tag = encoded.clone();
+        wrong_tag[0] = Tag::PartialSignature as u8;
+        match AggMessage1::deserialize(&wrong_tag) {
+            Err(Error::AtOffset { offset: 0, error }) => assert!(matches!(*error, Error::WrongTag { .. })),
+            other => panic!("expected a wrong tag at offset 0, got: {:?}", other),
+        }
+        // y = 2 isn't on the curve, so this is an invalid point in the second nonce.
+        let mut bad_point = encoded.clone();
+        bad_point[33..65].fill(0);
+        bad_point[33] = 2;
+        match AggMessage1::deserialize(&bad_point) {
+            Err(Error::AtOffset { offset: 33, error }) => assert!(matches!(*error, Error::InvalidPoint(_))),
+            other => panic!("expected an invalid point at offset 33, got: {:?}", other),
+        }
+    }
+
+    fn check_encodings<T: Serialize + PartialEq + Debug>(t: T) {
+        for encoding in [Encoding::Base58, Encoding::Base64, Encoding::Hex, Encoding::Json] {
+            let encoded = t.encode(encoding);
+            assert_eq!(PanicEq(&T::decode_with(&encoded, encoding).unwrap()), PanicEq(&t));
+            assert_eq!(PanicEq(&T::decode(&encoded).unwrap()), PanicEq(&t));
+        }
+    }
+
+    #[test]
+    fn test_secret_agg1_zeroize() {
+        let (private_nonces, public_nonces) = musig2::generate_partial_nonces(&ExpandedKeyPair::create(), None);
+        let mut secret_agg1 = SecretAggStepOne { private_nonces, public_nonces };
+        assert!(format!("{:?}", secret_agg1).contains("private_nonces: \"<redacted>\""));
+        secret_agg1.zeroize();
+        assert!(secret_agg1.private_nonces.r.iter().all(|r| r == &Scalar::zero()));
+    }
+
+    #[test]
+    fn test_secret_agg1_take_and_drop() {
+        let (private_nonces, public_nonces) = musig2::generate_partial_nonces(&ExpandedKeyPair::create(), None);
+        let expected = private_nonces.r.clone();
+        let mut secret_agg1 = SecretAggStepOne { private_nonces, public_nonces };
+        assert_eq!(secret_agg1.take_private_nonces().r, expected);
+        assert!(secret_agg1.private_nonces.r.iter().all(|r| r == &Scalar::zero()));
+
+        // Drop it in place, so its bytes are still there to look at.
+        let (private_nonces, public_nonces) = musig2::generate_partial_nonces(&ExpandedKeyPair::create(), None);
+        let mut secret_agg1 = MaybeUninit::new(SecretAggStepOne { private_nonces, public_nonces });
+        let zero = Scalar::<Ed25519>::zero();
+        let bytes = |scalar: &Scalar<Ed25519>| unsafe {
+            std::slice::from_raw_parts(scalar as *const Scalar<Ed25519> as *const u8, mem::size_of_val(scalar)).to_vec()
+        };
+        unsafe {
+            ptr::drop_in_place(secret_agg1.as_mut_ptr());
+            let nonces = &(*secret_agg1.as_ptr()).private_nonces.r;
+            assert!(nonces.iter().all(|r| bytes(r) == bytes(&zero)));
+        }
+    }
+
+    #[test]
+    fn test_encodings() {
+        let mut data = [0u8; 32];
+        for i in 0..u8::MAX {
+            data.fill(i);
+            let (private_nonces, public_nonces) =
+                musig2::generate_partial_nonces(&ExpandedKeyPair::create(), Some(&data));
+            check_encodings(AggMessage1 { public_nonces: public_nonces.clone(), sender: Pubkey::new(&data) });
+            check_encodings(SecretAggStepOne { private_nonces, public_nonces });
+            check_encodings(PartialSignature(Signature::new(&[i; 64])));
+        }
+    }
+
+    #[test]
+    fn test_json_has_named_fields() {
+        let partial_sig = PartialSignature(Signature::new(&[1u8; 64]));
+        let json: serde_json::Value = serde_json::from_str(&partial_sig.encode(Encoding::Json)).unwrap();
+        assert_eq!(json["type"], "partial_signature");
+        assert_eq!(json["signature"], partial_sig.0.to_string());
+
+        let wrong_type = serde_json::json!({ "type": "agg_message1", "signature": partial_sig.0.to_string() });
+        assert!(matches!(
+            PartialSignature::from_json(&wrong_type),
+            Err(Error::WrongTag { expected: Tag::PartialSignature, found: Tag::AggMessage1 })
+        ));
+        assert!(matches!(
+            PartialSignature::decode(r#"{"type": "partial_signature"}"#),
+            Err(Error::InvalidJsonField("signature"))
+        ));
+        assert!(matches!(PartialSignature::decode("not a message!"), Err(Error::UnrecognizedEncoding)));
+    }
 }
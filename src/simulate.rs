@@ -0,0 +1,134 @@
+use std::fmt::{Display, Formatter};
+
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::RpcSimulateTransactionConfig;
+use solana_sdk::transaction::{Transaction, TransactionError};
+
+use crate::Error;
+
+/// The outcome of running a transaction against the current state of the chain without committing it.
+#[derive(Debug)]
+pub struct Simulation {
+    pub err: Option<TransactionError>,
+    pub logs: Vec<String>,
+    pub units_consumed: Option<u64>,
+}
+
+impl Simulation {
+    /// Fail if the transaction would fail on chain.
+    pub fn into_result(self) -> Result<Self, Error> {
+        match self.err {
+            Some(e) => Err(Error::TransactionWouldFail(e)),
+            None => Ok(self),
+        }
+    }
+}
+
+impl Display for Simulation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.err {
+            None => writeln!(f, "Simulation succeeded")?,
+            Some(e) => writeln!(f, "Simulation failed: {}", e)?,
+        }
+        if let Some(units) = self.units_consumed {
+            writeln!(f, "Compute units consumed: {}", units)?;
+        }
+        writeln!(f, "Program logs:")?;
+        for log in &self.logs {
+            writeln!(f, "  {}", log)?;
+        }
+        Ok(())
+    }
+}
+
+/// Simulate `tx`, pass `sig_verify = false` to simulate a transaction that isn't signed yet.
+pub fn simulate(rpc_client: &RpcClient, tx: &Transaction, sig_verify: bool) -> Result<Simulation, Error> {
+    let config = RpcSimulateTransactionConfig {
+        sig_verify,
+        commitment: Some(rpc_client.commitment()),
+        ..RpcSimulateTransactionConfig::default()
+    };
+    let result = rpc_client.simulate_transaction_with_config(tx, config).map_err(Error::SimulationFailed)?.value;
+    let logs = result.logs.unwrap_or_default();
+    let units_consumed = units_consumed(&logs);
+    Ok(Simulation { err: result.err, logs, units_consumed })
+}
+
+/// Sum the compute units consumed by the top level instructions, as reported in the program logs.
+/// Inner invocations are already included in the units of the instruction that invoked them.
+fn units_consumed(logs: &[String]) -> Option<u64> {
+    let mut depth = 0usize;
+    let mut total = None;
+    for log in logs {
+        let mut words = log.split_whitespace();
+        match (words.next(), words.next(), words.next(), words.next()) {
+            (Some("Program"), Some(_), Some("invoke"), Some(_)) => depth += 1,
+            (Some("Program"), Some(_), Some("success"), None) | (Some("Program"), Some(_), Some("failed:"), _) => {
+                depth = depth.saturating_sub(1)
+            }
+            (Some("Program"), Some(_), Some("consumed"), Some(units)) if depth == 1 => {
+                if let Ok(units) = units.parse::<u64>() {
+                    total = Some(total.unwrap_or(0) + units);
+                }
+            }
+            _ => {}
+        }
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::native_token::LAMPORTS_PER_SOL;
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::transaction::TransactionError;
+    use solana_streamer::socket::SocketAddrSpace;
+    use solana_test_validator::TestValidator;
+
+    use crate::create_unsigned_transaction;
+    use crate::simulate::{simulate, units_consumed};
+
+    #[test]
+    fn test_units_consumed() {
+        let logs: Vec<_> = [
+            "Program 11111111111111111111111111111111 invoke [1]",
+            "Program 11111111111111111111111111111111 success",
+            "Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA invoke [1]",
+            "Program log: Instruction: Transfer",
+            "Program ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL invoke [2]",
+            "Program ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL consumed 1000 of 190000 compute units",
+            "Program ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL success",
+            "Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA consumed 3000 of 200000 compute units",
+            "Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA success",
+            "Program MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr invoke [1]",
+            "Program MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr consumed 500 of 197000 compute units",
+            "Program MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr failed: custom program error: 0x1",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert_eq!(units_consumed(&logs), Some(3500));
+        assert_eq!(units_consumed(&logs[..2]), None);
+    }
+
+    #[test]
+    fn test_simulate_unsigned() {
+        let payer = Pubkey::new_unique();
+        let testnet = TestValidator::with_no_fees(payer, None, SocketAddrSpace::Unspecified);
+        let rpc_client = testnet.get_rpc_client();
+        let recent_block_hash = rpc_client.get_latest_blockhash().unwrap();
+
+        let mut tx =
+            create_unsigned_transaction(LAMPORTS_PER_SOL, &Pubkey::new_unique(), Some("hi".to_string()), &payer);
+        tx.message.recent_blockhash = recent_block_hash;
+        let simulation = simulate(&rpc_client, &tx, false).unwrap().into_result().unwrap();
+        assert!(simulation.logs.iter().any(|log| log.contains(&spl_memo::id().to_string())));
+
+        let balance = rpc_client.get_balance(&payer).unwrap();
+        let mut tx = create_unsigned_transaction(balance + 1, &Pubkey::new_unique(), None, &payer);
+        tx.message.recent_blockhash = recent_block_hash;
+        let simulation = simulate(&rpc_client, &tx, false).unwrap();
+        assert!(matches!(simulation.err, Some(TransactionError::InstructionError(0, _))));
+        assert!(simulation.into_result().is_err());
+    }
+}
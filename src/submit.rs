@@ -0,0 +1,149 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use solana_sdk::clock::Slot;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{Transaction, TransactionError};
+
+use crate::chain::Chain;
+use crate::Error;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const REBROADCAST_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The definitive fate of a submitted transaction.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Submission {
+    /// The transaction is on chain, `err` is set if it landed but failed (the fee is still charged).
+    Landed { signature: Signature, slot: Slot, err: Option<TransactionError> },
+    /// The block hash expired and the transaction isn't on chain, so it never will be.
+    /// It's safe to sign the same payments again with a newer block hash.
+    Expired { signature: Signature },
+}
+
+impl Submission {
+    /// Fail unless the transaction landed and succeeded.
+    pub fn into_result(self) -> Result<(Signature, Slot), Error> {
+        match self {
+            Self::Landed { signature, slot, err: None } => Ok((signature, slot)),
+            Self::Landed { signature, err: Some(error), .. } => Err(Error::TransactionFailed { signature, error }),
+            Self::Expired { signature } => Err(Error::TransactionExpired(signature)),
+        }
+    }
+}
+
+/// Send `tx` and keep rebroadcasting it until it reaches the client's commitment or its block hash expires.
+/// The hash has expired once the finalized block height is past its last valid block height, from then on no fork can
+/// include the transaction, so a status lookup that still misses it tells for sure that it didn't land.
+/// A hash the node doesn't know isn't considered expired, it may come from a node ahead of this one.
+/// Gives up with `ConfirmationTimeout` after `timeout`, which is the only outcome that isn't definitive.
+pub fn submit(chain: &dyn Chain, tx: &Transaction, timeout: Duration) -> Result<Submission, Error> {
+    let start = Instant::now();
+    let hash = tx.message.recent_blockhash;
+    let mut last_valid_block_height = chain.last_valid_block_height(&hash)?;
+    let signature = chain.send(tx)?;
+    let mut last_broadcast = Instant::now();
+
+    loop {
+        match chain.status(&signature)? {
+            Some(status) if status.committed => {
+                return Ok(Submission::Landed { signature, slot: status.slot, err: status.err })
+            }
+            // It was processed, wait until it reaches the commitment or gets dropped with its fork.
+            Some(_) => {}
+            None if last_broadcast.elapsed() >= REBROADCAST_INTERVAL => {
+                if last_valid_block_height.is_none() {
+                    last_valid_block_height = chain.last_valid_block_height(&hash)?;
+                }
+                let expired = match last_valid_block_height {
+                    Some(last_valid) => chain.finalized_block_height()? > last_valid,
+                    None => false,
+                };
+                if expired {
+                    // It could have landed since the lookup above.
+                    if chain.status(&signature)?.is_none() {
+                        return Ok(Submission::Expired { signature });
+                    }
+                    continue;
+                }
+                // A failed rebroadcast isn't fatal, the original one might still land.
+                if let Err(e) = chain.send(tx) {
+                    eprintln!("Failed rebroadcasting {}: {}", signature, e);
+                }
+                last_broadcast = Instant::now();
+                continue;
+            }
+            None => {}
+        }
+        if start.elapsed() >= timeout {
+            return Err(Error::ConfirmationTimeout(signature));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use solana_sdk::hash::Hash;
+    use solana_sdk::native_token::LAMPORTS_PER_SOL;
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::signature::{Keypair, Signer};
+    use solana_streamer::socket::SocketAddrSpace;
+    use solana_test_validator::TestValidator;
+
+    use crate::chain::Chain;
+    use crate::create_unsigned_transaction;
+    use crate::mock::MockChain;
+    use crate::submit::{submit, Submission};
+    use crate::Error;
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_submit() {
+        let payer = Keypair::new();
+        let testnet = TestValidator::with_no_fees(payer.pubkey(), None, SocketAddrSpace::Unspecified);
+        let rpc_client = testnet.get_rpc_client();
+        let to = Pubkey::new_unique();
+
+        let mut tx = create_unsigned_transaction(LAMPORTS_PER_SOL, &to, None, &payer.pubkey());
+        tx.sign(&[&payer], rpc_client.get_latest_blockhash().unwrap());
+        let submission = submit(&rpc_client, &tx, TIMEOUT).unwrap();
+        assert!(matches!(submission, Submission::Landed { err: None, .. }));
+        assert_eq!(submission.into_result().unwrap().0, tx.signatures[0]);
+        assert_eq!(rpc_client.get_balance(&to).unwrap(), LAMPORTS_PER_SOL);
+
+        // The node may just be behind on a block hash it doesn't know, so that's never taken as expired.
+        let mut tx = create_unsigned_transaction(LAMPORTS_PER_SOL, &to, None, &payer.pubkey());
+        tx.sign(&[&payer], Hash::new_unique());
+        let result = submit(&rpc_client, &tx, Duration::from_secs(5));
+        assert!(matches!(result, Err(Error::ConfirmationTimeout(signature)) if signature == tx.signatures[0]));
+        assert_eq!(rpc_client.get_balance(&to).unwrap(), LAMPORTS_PER_SOL);
+    }
+
+    #[test]
+    fn test_rebroadcast() {
+        let chain = MockChain::new(0);
+        let payer = Keypair::new();
+        chain.fund(&payer.pubkey(), LAMPORTS_PER_SOL);
+        let to = Pubkey::new_unique();
+
+        // The first send gets lost, the rebroadcast lands.
+        let mut tx = create_unsigned_transaction(1, &to, None, &payer.pubkey());
+        tx.sign(&[&payer], chain.latest_blockhash().unwrap());
+        chain.drop_sends(1);
+        assert!(matches!(submit(&chain, &tx, TIMEOUT).unwrap(), Submission::Landed { err: None, .. }));
+        assert_eq!(chain.balance(&to).unwrap(), 1);
+
+        // The hash expires before the transaction gets through.
+        let mut tx = create_unsigned_transaction(2, &to, None, &payer.pubkey());
+        tx.sign(&[&payer], chain.latest_blockhash().unwrap());
+        chain.expire_after_sends(1);
+        let submission = submit(&chain, &tx, TIMEOUT).unwrap();
+        assert_eq!(submission, Submission::Expired { signature: tx.signatures[0] });
+        assert!(matches!(submission.into_result(), Err(Error::TransactionExpired(_))));
+        assert_eq!(chain.balance(&to).unwrap(), 1);
+    }
+}
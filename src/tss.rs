@@ -1,16 +1,119 @@
 #![allow(non_snake_case)]
 
+use curv::arithmetic::Converter;
 use curv::elliptic::curves::{Ed25519, Point, Scalar};
-use multi_party_eddsa::protocols::musig2::{self, PrivatePartialNonces, PublicPartialNonces};
+use curv::BigInt;
+use multi_party_eddsa::protocols::musig2;
 use multi_party_eddsa::protocols::ExpandedKeyPair;
-use solana_sdk::signature::{Keypair, Signature, Signer, SignerError};
+use ring::digest::{Context, SHA512};
+use solana_sdk::message::Message;
+use solana_sdk::signature::{Keypair, Signature, Signer};
 use solana_sdk::{hash::Hash, pubkey::Pubkey, transaction::Transaction};
+use zeroize::Zeroizing;
 
+use crate::batch::Payment;
+use crate::policy::Policy;
+use crate::secret::Secret;
 use crate::serialization::{AggMessage1, Error as DeserializationError, PartialSignature, SecretAggStepOne};
-use crate::{create_unsigned_transaction, Error};
+use crate::{batch_instructions, Error};
 
-/// Create the aggregate public key, pass key=None if you don't care about the coefficient
+/// The transaction details all the parties *must* agree on before signing.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionParams {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_support::display_fromstr_vec"))]
+    pub keys: Vec<Pubkey>,
+    pub payments: Vec<Payment>,
+    pub memo: Option<String>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_support::display_fromstr"))]
+    pub recent_block_hash: Hash,
+    /// Sign for the address derived from the aggregate key by this label, see [`tweak_key`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub label: Option<String>,
+    /// Pay the fee from this address instead of the aggregate address, it then has to sign too.
+    #[cfg_attr(feature = "serde", serde(default, with = "crate::serde_support::display_fromstr_option"))]
+    pub fee_payer: Option<Pubkey>,
+}
+
+/// The result of aggregating a set of keys.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AggregateKey {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_support::display_fromstr_vec"))]
+    pub keys: Vec<Pubkey>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_support::display_fromstr"))]
+    pub aggregate: Pubkey,
+}
+
+/// Separates the hash of the key tweak from any other use of SHA-512.
+const TWEAK_DOMAIN: &[u8] = b"solana-tss/tweak";
+
+/// Aggregate a list of keys into the single address they can all sign on together, or into the address of `label`.
+/// The keys in the result are in their canonical order.
+pub fn aggregate_keys(keys: Vec<Pubkey>, label: Option<&str>) -> Result<AggregateKey, Error> {
+    let keys = canonical_keys(keys)?;
+    let mut aggkey = key_agg(keys.clone(), None)?.agg_public_key;
+    if let Some(label) = label {
+        aggkey = tweak_key(&aggkey, label).0;
+    }
+    let aggregate = Pubkey::new(&*aggkey.to_bytes(true));
+    Ok(AggregateKey { keys, aggregate })
+}
+
+/// Derive the address of `label` from the aggregate key X: X' = X + t·G where t = SHA-512(domain || X || label) mod l.
+/// Returns X' and t. The same parties sign for X' with their shares of X, the aggregator adds the public part c·t.
+pub fn tweak_key(aggkey: &Point<Ed25519>, label: &str) -> (Point<Ed25519>, Scalar<Ed25519>) {
+    let tweak = hash_to_scalar(&[TWEAK_DOMAIN, &*aggkey.to_bytes(true), label.as_bytes()]);
+    (aggkey + Point::generator() * &tweak, tweak)
+}
+
+/// SHA-512 of the parts reduced mod l, reading the hash as a little endian number like ed25519 does.
+fn hash_to_scalar(parts: &[&[u8]]) -> Scalar<Ed25519> {
+    let mut hasher = Context::new(&SHA512);
+    for part in parts {
+        hasher.update(part);
+    }
+    let mut hash = hasher.finish().as_ref().to_vec();
+    hash.reverse();
+    Scalar::from_bigint(&BigInt::from_bytes(&hash))
+}
+
+/// Sort the keys, so that the same set of parties always gets the same aggregate key no matter the order they're listed in.
+/// A key that shows up more than once is rejected, as it would silently produce a different address.
+pub fn canonical_keys(mut keys: Vec<Pubkey>) -> Result<Vec<Pubkey>, Error> {
+    keys.sort_unstable();
+    if let Some(duplicate) = keys.windows(2).find(|pair| pair[0] == pair[1]) {
+        return Err(Error::DuplicateKey(duplicate[0]));
+    }
+    Ok(keys)
+}
+
+/// Build the exact transaction the parties are signing in this session, with the recent block hash in place.
+pub fn session_transaction(session: &SessionParams) -> Result<Transaction, Error> {
+    let aggpubkey = aggregate_keys(session.keys.clone(), session.label.as_deref())?.aggregate;
+    let instructions = batch_instructions(&session.payments, session.memo.clone(), &aggpubkey);
+    let mut message = Message::new(&instructions, Some(session.fee_payer.as_ref().unwrap_or(&aggpubkey)));
+    message.recent_blockhash = session.recent_block_hash;
+    Ok(Transaction::new_unsigned(message))
+}
+
+/// Create the aggregate public key, pass key=None if you don't care about the coefficient.
+/// The keys are put in their canonical order first, see [`canonical_keys`].
 pub fn key_agg(keys: Vec<Pubkey>, key: Option<Pubkey>) -> Result<musig2::PublicKeyAgg, Error> {
+    ordered_key_agg(canonical_keys(keys)?, key)
+}
+
+/// The address versions before [`canonical_keys`] aggregated the keys into, in the order they were listed.
+/// `None` if that order is the canonical one, so the address didn't change.
+pub fn legacy_aggregate(keys: &[Pubkey]) -> Result<Option<Pubkey>, Error> {
+    if canonical_keys(keys.to_vec())? == keys {
+        return Ok(None);
+    }
+    let aggkey = ordered_key_agg(keys.to_vec(), None)?.agg_public_key;
+    Ok(Some(Pubkey::new(&*aggkey.to_bytes(true))))
+}
+
+fn ordered_key_agg(keys: Vec<Pubkey>, key: Option<Pubkey>) -> Result<musig2::PublicKeyAgg, Error> {
     let convert_keys = |k: Pubkey| {
         Point::from_bytes(&k.to_bytes()).map_err(|e| Error::DeserializationFailed {
             error: DeserializationError::InvalidPoint(e),
@@ -22,9 +125,30 @@ pub fn key_agg(keys: Vec<Pubkey>, key: Option<Pubkey>) -> Result<musig2::PublicK
     musig2::PublicKeyAgg::key_aggregation_n(keys, &key).ok_or(Error::KeyPairIsNotInKeys)
 }
 
+/// Expand the keypair into the form musig2 works with, keeping the result in protected memory.
+fn expand_keypair(keypair: &Keypair) -> Secret<ExpandedKeyPair> {
+    let secret = Zeroizing::new(keypair.secret().to_bytes());
+    Secret::from_fn(|| ExpandedKeyPair::create_from_private_key(*secret))
+}
+
+/// The aggregate key of `keys`, tweaked by `label` if there's one, along with the tweak.
+fn tweaked_key_agg(
+    keys: &[Pubkey],
+    label: Option<&str>,
+    key: Option<Pubkey>,
+) -> Result<(musig2::PublicKeyAgg, Option<Scalar<Ed25519>>), Error> {
+    let mut aggkey = key_agg(keys.to_vec(), key)?;
+    let tweak = label.map(|label| {
+        let (tweaked, tweak) = tweak_key(&aggkey.agg_public_key, label);
+        aggkey.agg_public_key = tweaked;
+        tweak
+    });
+    Ok((aggkey, tweak))
+}
+
 /// Generate Message1 which contains nonce, public nonce, and commitment to nonces
 pub fn step_one(keypair: Keypair) -> (AggMessage1, SecretAggStepOne) {
-    let extended_kepair = ExpandedKeyPair::create_from_private_key(keypair.secret().to_bytes());
+    let extended_kepair = expand_keypair(&keypair);
     // we don't really need to pass a message here.
     let (private_nonces, public_nonces) = musig2::generate_partial_nonces(&extended_kepair, None);
 
@@ -34,49 +158,75 @@ pub fn step_one(keypair: Keypair) -> (AggMessage1, SecretAggStepOne) {
     )
 }
 
-#[allow(clippy::too_many_arguments)]
 pub fn step_two(
     keypair: Keypair,
-    amount: f64,
-    to: Pubkey,
-    memo: Option<String>,
-    recent_block_hash: Hash,
-    keys: Vec<Pubkey>,
+    session: &SessionParams,
+    first_messages: Vec<AggMessage1>,
+    secret_state: SecretAggStepOne,
+    policy: &mut Policy,
+) -> Result<PartialSignature, Error> {
+    let tx = session_transaction(session)?;
+    policy.check(session, &tx)?;
+    let sig =
+        sign_message(keypair, &session.keys, session.label.as_deref(), &tx.message, first_messages, secret_state)?;
+    policy.record(session)?;
+    Ok(sig)
+}
+
+/// Partially sign any `message` the aggregate key of `keys` (tweaked by `label`) is a signer of.
+pub fn sign_message(
+    keypair: Keypair,
+    keys: &[Pubkey],
+    label: Option<&str>,
+    message: &Message,
     first_messages: Vec<AggMessage1>,
     secret_state: SecretAggStepOne,
 ) -> Result<PartialSignature, Error> {
     let other_nonces: Vec<_> = first_messages.into_iter().map(|msg1| msg1.public_nonces.R).collect();
 
     // Generate the aggregate key together with the coefficient of the current keypair
-    let aggkey = key_agg(keys, Some(keypair.pubkey()))?;
+    let (aggkey, _) = tweaked_key_agg(keys, label, Some(keypair.pubkey()))?;
     let aggpubkey = Pubkey::new(&*aggkey.agg_public_key.to_bytes(true));
-    let extended_kepair = ExpandedKeyPair::create_from_private_key(keypair.secret().to_bytes());
+    if !message.signer_keys().contains(&&aggpubkey) {
+        return Err(Error::NotASigner(aggpubkey));
+    }
+    let extended_kepair = expand_keypair(&keypair);
 
-    // Create the unsigned transaction
-    let mut tx = create_unsigned_transaction(amount, &to, memo, &aggpubkey);
+    let signer = PartialSigner { secret_state, other_nonces, extended_kepair, aggregated_pubkey: aggkey };
+    Ok(PartialSignature(signer.sign(&message.serialize())))
+}
 
-    let signer = PartialSigner {
-        signer_private_nonce: secret_state.private_nonces,
-        signer_public_nonce: secret_state.public_nonces,
-        other_nonces,
-        extended_kepair,
-        aggregated_pubkey: aggkey,
-    };
-    // Sign the transaction using a custom `PartialSigner`, this is required to comply with Solana's API.
-    tx.sign(&[&signer], recent_block_hash);
-    let sig = tx.signatures[0];
-    Ok(PartialSignature(sig))
-}
-
-pub fn sign_and_broadcast(
-    amount: f64,
-    to: Pubkey,
-    memo: Option<String>,
-    recent_block_hash: Hash,
-    keys: Vec<Pubkey>,
+/// Aggregate the partial signatures into the session's transaction.
+/// Any other signer, like a separate fee payer, still has to be added, see `check_complete`.
+pub fn sign_and_broadcast(session: &SessionParams, signatures: Vec<PartialSignature>) -> Result<Transaction, Error> {
+    let mut tx = session_transaction(session)?;
+    add_aggregate_signature(&mut tx, &session.keys, session.label.as_deref(), signatures)?;
+    Ok(tx)
+}
+
+/// Aggregate the partial signatures of the aggregate key of `keys` (tweaked by `label`) into its slot of `tx`.
+pub fn add_aggregate_signature(
+    tx: &mut Transaction,
+    keys: &[Pubkey],
+    label: Option<&str>,
     signatures: Vec<PartialSignature>,
-) -> Result<Transaction, Error> {
-    let aggkey = key_agg(keys, None)?;
+) -> Result<(), Error> {
+    let aggpubkey = aggregate_keys(keys.to_vec(), label)?.aggregate;
+    let signature = aggregate_signatures(keys, label, &tx.message, signatures)?;
+    add_signature(tx, &aggpubkey, signature)
+}
+
+/// Add up the partial signatures of `message` into the signature of the aggregate key, and check it.
+pub fn aggregate_signatures(
+    keys: &[Pubkey],
+    label: Option<&str>,
+    message: &Message,
+    signatures: Vec<PartialSignature>,
+) -> Result<Signature, Error> {
+    if signatures.len() < 2 || signatures.len() != keys.len() {
+        return Err(Error::WrongSignatureCount { keys: keys.len(), signatures: signatures.len() });
+    }
+    let (aggkey, tweak) = tweaked_key_agg(keys, label, None)?;
     let aggpubkey = Pubkey::new(&*aggkey.agg_public_key.to_bytes(true));
 
     // Make sure all the `R`s are the same
@@ -106,44 +256,150 @@ pub fn sign_and_broadcast(
 
     // Add the signatures up
     let full_sig = musig2::aggregate_partial_signatures(&first_sig, &partial_sigs);
+    let message = message.serialize();
+
+    // The shares sign for x, the tweaked key is x + t, so the signature is missing c·t where c = H(R || X' || m).
+    let mut s = full_sig.s;
+    if let Some(tweak) = tweak {
+        let R = full_sig.R.to_bytes(true);
+        let challenge = hash_to_scalar(&[&*R, &*aggkey.agg_public_key.to_bytes(true), &message]);
+        s = s + challenge * tweak;
+    }
 
     let mut sig_bytes = [0u8; 64];
     sig_bytes[..32].copy_from_slice(&*full_sig.R.to_bytes(true));
-    sig_bytes[32..].copy_from_slice(&full_sig.s.to_bytes());
+    sig_bytes[32..].copy_from_slice(&s.to_bytes());
     let sig = Signature::new(&sig_bytes);
+    if !sig.verify(aggpubkey.as_ref(), &message) {
+        return Err(Error::InvalidSignature);
+    }
+    Ok(sig)
+}
+
+/// Check the partial signature of `signer` over `message`, given the first messages of every party, its own included.
+/// When the aggregated signature doesn't verify, this tells which party's output is wrong.
+pub fn verify_partial_signature(
+    keys: &[Pubkey],
+    label: Option<&str>,
+    signer: &Pubkey,
+    message: &[u8],
+    first_messages: &[AggMessage1],
+    signature: &PartialSignature,
+) -> Result<(), Error> {
+    let invalid = |reason: &str| Error::InvalidPartialSignature { signer: *signer, reason: reason.to_string() };
+    let (mut aggkey, _) = tweaked_key_agg(keys, label, Some(*signer))?;
+    let senders = canonical_keys(first_messages.iter().map(|msg1| msg1.sender).collect())?;
+    if senders != canonical_keys(keys.to_vec())? {
+        return Err(invalid("the first messages don't come from exactly the listed keys"));
+    }
+    let own_nonces = first_messages.iter().find(|msg1| msg1.sender == *signer).expect("the signer is in the keys");
+    let other_nonces: Vec<_> =
+        first_messages.iter().filter(|msg1| msg1.sender != *signer).map(|msg1| msg1.public_nonces.R.clone()).collect();
 
-    // Create the same transaction again
-    let mut tx = create_unsigned_transaction(amount, &to, memo, &aggpubkey);
-    // Insert the recent_block_hash and the signature to the right places
-    tx.message.recent_blockhash = recent_block_hash;
-    assert_eq!(tx.signatures.len(), 1);
-    tx.signatures[0] = sig;
+    // musig2 doesn't expose the nonce coefficient b, so have it sign with the private nonces (0, 1) and no key share:
+    // the result is then s = 0 + b·1 + c·0·x = b, next to the effective nonce R of the whole session.
+    let coefficient = std::mem::replace(&mut aggkey.musig_coefficient, Scalar::zero());
+    let probe_nonces = musig2::PrivatePartialNonces { r: [Scalar::zero(), Scalar::from_bigint(&BigInt::from(1u64))] };
+    let probe = musig2::partial_sign(
+        &other_nonces,
+        probe_nonces,
+        own_nonces.public_nonces.clone(),
+        &aggkey,
+        &ExpandedKeyPair::create(),
+        message,
+    );
+    let (R, b) = (probe.R, probe.my_partial_s);
+
+    if signature.0.as_ref()[..32] != *R.to_bytes(true) {
+        return Err(invalid("it was made with other nonces, or for another message or key set"));
+    }
+    let s = Scalar::from_bytes(&signature.0.as_ref()[32..]).map_err(|e| Error::DeserializationFailed {
+        error: DeserializationError::InvalidScalar(e),
+        field_name: "signature",
+    })?;
+    let public_key = Point::from_bytes(&signer.to_bytes()).map_err(|e| Error::DeserializationFailed {
+        error: DeserializationError::InvalidPoint(e),
+        field_name: "signer",
+    })?;
+
+    // s·G = R₁ + b·R₂ + c·a·X, where c = H(R || X' || m) is the challenge of the (tweaked) aggregate key X'.
+    let challenge = hash_to_scalar(&[&*R.to_bytes(true), &*aggkey.agg_public_key.to_bytes(true), message]);
+    let [R1, R2] = &own_nonces.public_nonces.R;
+    if Point::generator() * &s != R1 + R2 * &b + public_key * (challenge * coefficient) {
+        return Err(invalid("it doesn't match the signer's key and nonces"));
+    }
+    Ok(())
+}
+
+/// Put the aggregate `signature` in the slot of `aggregate`, and sign with the other `signers`,
+/// e.g. a separate fee payer. Fails unless every signature the message needs is there.
+pub fn assemble_transaction(
+    message: Message,
+    aggregate: &Pubkey,
+    signature: Signature,
+    signers: &[Keypair],
+) -> Result<Transaction, Error> {
+    let mut tx = Transaction::new_unsigned(message);
+    add_signature(&mut tx, aggregate, signature)?;
+    for signer in signers {
+        add_signer(&mut tx, signer)?;
+    }
+    check_complete(&tx)?;
+    Ok(tx)
+}
+
+/// Put the `signature` of `key` into its slot, after checking it signs the message.
+pub fn add_signature(tx: &mut Transaction, key: &Pubkey, signature: Signature) -> Result<(), Error> {
+    let slot = tx.message.signer_keys().iter().position(|signer| *signer == key).ok_or(Error::NotASigner(*key))?;
+    if !signature.verify(key.as_ref(), &tx.message_data()) {
+        return Err(Error::InvalidSignature);
+    }
+    tx.signatures[slot] = signature;
+    Ok(())
+}
+
+/// Sign with an ordinary keypair, e.g. a fee payer or a new account.
+pub fn add_signer(tx: &mut Transaction, signer: &Keypair) -> Result<(), Error> {
+    let signature = signer.sign_message(&tx.message_data());
+    add_signature(tx, &signer.pubkey(), signature)
+}
 
-    // Make sure the resulting transaction is actually valid.
+/// The signers whose signature is still missing.
+pub fn missing_signers(tx: &Transaction) -> Vec<Pubkey> {
+    let signer_keys = tx.message.signer_keys();
+    signer_keys
+        .into_iter()
+        .zip(&tx.signatures)
+        .filter(|(_, sig)| **sig == Signature::default())
+        .map(|(key, _)| *key)
+        .collect()
+}
+
+/// Fail unless every signer signed, and every signature is valid.
+pub fn check_complete(tx: &Transaction) -> Result<(), Error> {
+    if let Some(missing) = missing_signers(tx).first() {
+        return Err(Error::MissingSigner(*missing));
+    }
     if tx.verify().is_err() {
         return Err(Error::InvalidSignature);
     }
-    Ok(tx)
+    Ok(())
 }
 
 struct PartialSigner {
-    signer_private_nonce: PrivatePartialNonces,
-    signer_public_nonce: PublicPartialNonces,
+    secret_state: SecretAggStepOne,
     other_nonces: Vec<[Point<Ed25519>; 2]>,
-    extended_kepair: ExpandedKeyPair,
+    extended_kepair: Secret<ExpandedKeyPair>,
     aggregated_pubkey: musig2::PublicKeyAgg,
 }
 
-impl Signer for PartialSigner {
-    fn try_pubkey(&self) -> Result<Pubkey, SignerError> {
-        Ok(Pubkey::new(&*self.aggregated_pubkey.agg_public_key.to_bytes(true)))
-    }
-
-    fn try_sign_message(&self, message: &[u8]) -> Result<Signature, SignerError> {
+impl PartialSigner {
+    /// The nonces are moved into the signature rather than copied, they can only ever sign once.
+    fn sign(mut self, message: &[u8]) -> Signature {
         let sig = musig2::partial_sign(
             &self.other_nonces,
-            self.signer_private_nonce.clone(),
-            self.signer_public_nonce.clone(),
+            self.secret_state.take_private_nonces(),
+            self.secret_state.public_nonces.clone(),
             &self.aggregated_pubkey,
             &self.extended_kepair,
             message,
@@ -151,21 +407,27 @@ impl Signer for PartialSigner {
         let mut sig_bytes = [0u8; 64];
         sig_bytes[..32].copy_from_slice(&*sig.R.to_bytes(true));
         sig_bytes[32..].copy_from_slice(&sig.my_partial_s.to_bytes());
-        Ok(Signature::new(&sig_bytes))
-    }
-
-    fn is_interactive(&self) -> bool {
-        false
+        Signature::new(&sig_bytes)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::native_token::lamports_to_sol;
+    use crate::batch::Payment;
+    use crate::policy::Policy;
+    use crate::serialization::PartialSignature;
     use crate::serialization::Serialize;
-    use crate::tss::{key_agg, sign_and_broadcast, step_one, step_two};
+    use crate::tss::{
+        add_aggregate_signature, add_signer, aggregate_keys, check_complete, expand_keypair, key_agg, legacy_aggregate,
+        session_transaction, sign_and_broadcast, sign_message, step_one, step_two, tweaked_key_agg,
+        verify_partial_signature, PartialSigner, SessionParams,
+    };
+    use crate::Error;
+    use rand07::seq::SliceRandom;
+    use solana_sdk::hash::Hash;
+    use solana_sdk::message::Message;
     use solana_sdk::pubkey::Pubkey;
-    use solana_sdk::signature::{Keypair, Signer};
+    use solana_sdk::signature::{Keypair, Signature, Signer};
     use solana_streamer::socket::SocketAddrSpace;
     use solana_test_validator::TestValidator;
 
@@ -197,8 +459,14 @@ mod tests {
 
         let recent_block_hash = rpc_client.get_latest_blockhash().unwrap();
         // step 2
-        let amount = lamports_to_sol(full_amount / 2);
-        let memo = Some("test_roundtrip".to_string());
+        let session = SessionParams {
+            keys: pubkeys,
+            payments: vec![Payment { to: to.pubkey(), amount: full_amount / 2 }],
+            memo: Some("test_roundtrip".to_string()),
+            recent_block_hash,
+            label: None,
+            fee_payer: None,
+        };
 
         let partial_sigs: Vec<_> = keys
             .iter()
@@ -208,15 +476,226 @@ mod tests {
             .map(|(i, (key, secret))| {
                 let mut first_msgs: Vec<_> = first_msgs.iter().map(clone_serialize).collect();
                 first_msgs.remove(i);
-                step_two(key, amount, to.pubkey(), memo.clone(), recent_block_hash, pubkeys.clone(), first_msgs, secret)
-                    .unwrap()
+                step_two(key, &session, first_msgs, secret, &mut Policy::default()).unwrap()
             })
             .collect();
 
-        let full_tx = sign_and_broadcast(amount, to.pubkey(), memo, recent_block_hash, pubkeys, partial_sigs).unwrap();
+        let full_tx = sign_and_broadcast(&session, partial_sigs).unwrap();
         let sig = rpc_client.send_transaction(&full_tx).unwrap();
 
         // Wait for confirmation
         rpc_client.confirm_transaction_with_spinner(&sig, &recent_block_hash, rpc_client.commitment()).unwrap();
     }
+
+    #[test]
+    fn test_key_order_doesnt_matter() {
+        let mut rng = rand07::thread_rng();
+        for n in 2..8 {
+            let mut pubkeys: Vec<_> = (0..n).map(|_| Keypair::generate(&mut rng).pubkey()).collect();
+            let aggkey = aggregate_keys(pubkeys.clone(), None).unwrap();
+            for _ in 0..20 {
+                pubkeys.shuffle(&mut rng);
+                assert_eq!(aggregate_keys(pubkeys.clone(), None).unwrap(), aggkey);
+                let coefficient_key = *pubkeys.choose(&mut rng).unwrap();
+                let musig_key = key_agg(pubkeys.clone(), Some(coefficient_key)).unwrap();
+                assert_eq!(Pubkey::new(&*musig_key.agg_public_key.to_bytes(true)), aggkey.aggregate);
+            }
+            let mut sorted = pubkeys.clone();
+            sorted.sort();
+            assert_eq!(aggkey.keys, sorted);
+
+            // Only another order gives a legacy address, and it's not the new one.
+            assert_eq!(legacy_aggregate(&sorted).unwrap(), None);
+            sorted.reverse();
+            assert_ne!(legacy_aggregate(&sorted).unwrap().unwrap(), aggkey.aggregate);
+        }
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        let mut rng = rand07::thread_rng();
+        let pubkeys: Vec<_> = (0..3).map(|_| Keypair::generate(&mut rng).pubkey()).collect();
+        let mut with_duplicate = pubkeys.clone();
+        with_duplicate.push(pubkeys[1]);
+        with_duplicate.shuffle(&mut rng);
+        assert!(matches!(aggregate_keys(with_duplicate.clone(), None), Err(Error::DuplicateKey(k)) if k == pubkeys[1]));
+        assert!(matches!(key_agg(with_duplicate, Some(pubkeys[0])), Err(Error::DuplicateKey(k)) if k == pubkeys[1]));
+    }
+
+    #[test]
+    fn test_signing_with_different_key_orders() {
+        let n = 4;
+        let mut rng = rand07::thread_rng();
+        let keys: Vec<_> = (0..n).map(|_| Keypair::generate(&mut rng)).collect();
+        let pubkeys: Vec<_> = keys.iter().map(|k| k.pubkey()).collect();
+        let session = SessionParams {
+            keys: pubkeys,
+            payments: vec![Payment { to: Pubkey::new_unique(), amount: 1_000 }],
+            memo: None,
+            recent_block_hash: Hash::new_unique(),
+            label: None,
+            fee_payer: None,
+        };
+        let (first_msgs, first_secrets): (Vec<_>, Vec<_>) = keys.iter().map(clone_keypair).map(step_one).unzip();
+
+        // Every party lists the keys in its own order.
+        let partial_sigs: Vec<_> = keys
+            .iter()
+            .map(clone_keypair)
+            .zip(first_secrets.into_iter())
+            .enumerate()
+            .map(|(i, (key, secret))| {
+                let mut first_msgs: Vec<_> = first_msgs.iter().map(clone_serialize).collect();
+                first_msgs.remove(i);
+                let mut session = session.clone();
+                session.keys.shuffle(&mut rand07::thread_rng());
+                step_two(key, &session, first_msgs, secret, &mut Policy::default()).unwrap()
+            })
+            .collect();
+
+        let mut session = session;
+        session.keys.shuffle(&mut rng);
+        // `sign_and_broadcast` verifies the resulting signature.
+        sign_and_broadcast(&session, partial_sigs).unwrap();
+    }
+
+    #[test]
+    fn test_tweaked_signing() {
+        let mut rng = rand07::thread_rng();
+        let keys: Vec<_> = (0..3).map(|_| Keypair::generate(&mut rng)).collect();
+        let pubkeys: Vec<_> = keys.iter().map(|k| k.pubkey()).collect();
+        let untweaked = aggregate_keys(pubkeys.clone(), None).unwrap().aggregate;
+        let customer_1 = aggregate_keys(pubkeys.clone(), Some("customer-1")).unwrap().aggregate;
+        let customer_2 = aggregate_keys(pubkeys.clone(), Some("customer-2")).unwrap().aggregate;
+        assert_ne!(customer_1, untweaked);
+        assert_ne!(customer_1, customer_2);
+        assert_eq!(aggregate_keys(pubkeys.clone(), Some("customer-1")).unwrap().aggregate, customer_1);
+
+        let sign = |session: &SessionParams| -> Vec<_> {
+            let (first_msgs, first_secrets): (Vec<_>, Vec<_>) = keys.iter().map(clone_keypair).map(step_one).unzip();
+            keys.iter()
+                .map(clone_keypair)
+                .zip(first_secrets.into_iter())
+                .enumerate()
+                .map(|(i, (key, secret))| {
+                    let mut first_msgs: Vec<_> = first_msgs.iter().map(clone_serialize).collect();
+                    first_msgs.remove(i);
+                    step_two(key, session, first_msgs, secret, &mut Policy::default()).unwrap()
+                })
+                .collect()
+        };
+        let session = SessionParams {
+            keys: pubkeys,
+            payments: vec![Payment { to: Pubkey::new_unique(), amount: 1_000 }],
+            memo: None,
+            recent_block_hash: Hash::new_unique(),
+            label: Some("customer-1".to_string()),
+            fee_payer: None,
+        };
+        // `sign_and_broadcast` verifies the signature against the fee payer, the tweaked address.
+        let tx = sign_and_broadcast(&session, sign(&session)).unwrap();
+        assert_eq!(tx.message.account_keys[0], customer_1);
+
+        // Signatures for one label don't add up to a signature for another.
+        let other = SessionParams { label: Some("customer-2".to_string()), ..session.clone() };
+        assert!(matches!(sign_and_broadcast(&other, sign(&session)), Err(Error::InvalidSignature)));
+    }
+    /// Partially sign `message` as the aggregate key of `keys`.
+    fn sign_as(keys: &[Keypair], message: &Message) -> Vec<PartialSignature> {
+        let pubkeys: Vec<_> = keys.iter().map(|k| k.pubkey()).collect();
+        let (first_msgs, first_secrets): (Vec<_>, Vec<_>) = keys.iter().map(clone_keypair).map(step_one).unzip();
+        keys.iter()
+            .map(clone_keypair)
+            .zip(first_secrets.into_iter())
+            .enumerate()
+            .map(|(i, (key, secret))| {
+                let mut first_msgs: Vec<_> = first_msgs.iter().map(clone_serialize).collect();
+                first_msgs.remove(i);
+                sign_message(key, &pubkeys, None, message, first_msgs, secret).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_extra_signers() {
+        let mut rng = rand07::thread_rng();
+        let treasury: Vec<_> = (0..3).map(|_| Keypair::generate(&mut rng)).collect();
+        let treasury_pubkeys: Vec<_> = treasury.iter().map(|k| k.pubkey()).collect();
+        let mut session = SessionParams {
+            keys: treasury_pubkeys.clone(),
+            payments: vec![Payment { to: Pubkey::new_unique(), amount: 1_000 }],
+            memo: None,
+            recent_block_hash: Hash::new_unique(),
+            label: None,
+            fee_payer: None,
+        };
+
+        // A hot wallet pays the fee, its signature goes into the first slot.
+        let hot_wallet = Keypair::generate(&mut rng);
+        session.fee_payer = Some(hot_wallet.pubkey());
+        let message = session_transaction(&session).unwrap().message;
+        assert_eq!(message.account_keys[0], hot_wallet.pubkey());
+        let mut tx = sign_and_broadcast(&session, sign_as(&treasury, &message)).unwrap();
+        assert!(matches!(check_complete(&tx), Err(Error::MissingSigner(key)) if key == hot_wallet.pubkey()));
+        assert!(matches!(add_signer(&mut tx, &Keypair::new()), Err(Error::NotASigner(_))));
+        add_signer(&mut tx, &hot_wallet).unwrap();
+        check_complete(&tx).unwrap();
+
+        // Another aggregate key pays the fee, and signs on its own.
+        let operations: Vec<_> = (0..2).map(|_| Keypair::generate(&mut rng)).collect();
+        let operations_pubkeys: Vec<_> = operations.iter().map(|k| k.pubkey()).collect();
+        let operations_aggregate = aggregate_keys(operations_pubkeys.clone(), None).unwrap().aggregate;
+        session.fee_payer = Some(operations_aggregate);
+        let message = session_transaction(&session).unwrap().message;
+        let mut tx = sign_and_broadcast(&session, sign_as(&treasury, &message)).unwrap();
+        assert!(matches!(check_complete(&tx), Err(Error::MissingSigner(key)) if key == operations_aggregate));
+        add_aggregate_signature(&mut tx, &operations_pubkeys, None, sign_as(&operations, &message)).unwrap();
+        check_complete(&tx).unwrap();
+        assert_eq!(tx.signatures.len(), 2);
+    }
+    #[test]
+    fn test_verify_partial_signature() {
+        let mut rng = rand07::thread_rng();
+        let keys: Vec<_> = (0..3).map(|_| Keypair::generate(&mut rng)).collect();
+        let pubkeys: Vec<_> = keys.iter().map(|k| k.pubkey()).collect();
+        for label in [None, Some("customer-1")] {
+            let message = b"a message to sign";
+            let (first_msgs, first_secrets): (Vec<_>, Vec<_>) = keys.iter().map(clone_keypair).map(step_one).unzip();
+            let partial_sigs: Vec<_> = keys
+                .iter()
+                .map(clone_keypair)
+                .zip(first_secrets.into_iter())
+                .enumerate()
+                .map(|(i, (key, secret))| {
+                    let mut first_msgs: Vec<_> = first_msgs.iter().map(clone_serialize).collect();
+                    first_msgs.remove(i);
+                    let signer = PartialSigner {
+                        secret_state: secret,
+                        other_nonces: first_msgs.into_iter().map(|msg1| msg1.public_nonces.R).collect(),
+                        extended_kepair: expand_keypair(&key),
+                        aggregated_pubkey: tweaked_key_agg(&pubkeys, label, Some(key.pubkey())).unwrap().0,
+                    };
+                    PartialSignature(signer.sign(message))
+                })
+                .collect();
+
+            for (key, sig) in pubkeys.iter().zip(&partial_sigs) {
+                verify_partial_signature(&pubkeys, label, key, message, &first_msgs, sig).unwrap();
+            }
+            // Someone else's signature, another message, or a tampered signature are all caught.
+            let wrong_signer =
+                verify_partial_signature(&pubkeys, label, &pubkeys[1], message, &first_msgs, &partial_sigs[0]);
+            assert!(matches!(wrong_signer, Err(Error::InvalidPartialSignature { signer, .. }) if signer == pubkeys[1]));
+            let other_message =
+                verify_partial_signature(&pubkeys, label, &pubkeys[0], b"another", &first_msgs, &partial_sigs[0]);
+            assert!(matches!(other_message, Err(Error::InvalidPartialSignature { .. })));
+            let mut bytes = partial_sigs[0].0.as_ref().to_vec();
+            bytes[40] ^= 1;
+            let tampered = PartialSignature(Signature::new(&bytes));
+            assert!(verify_partial_signature(&pubkeys, label, &pubkeys[0], message, &first_msgs, &tampered).is_err());
+            let missing =
+                verify_partial_signature(&pubkeys, label, &pubkeys[0], message, &first_msgs[..2], &partial_sigs[0]);
+            assert!(matches!(missing, Err(Error::InvalidPartialSignature { .. })));
+        }
+    }
 }
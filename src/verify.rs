@@ -0,0 +1,130 @@
+//! Offline checks of signatures, without talking to the cluster.
+
+use std::fmt::{self, Display, Formatter};
+
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::Transaction;
+
+use crate::serialization::Encoding;
+use crate::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SignatureStatus {
+    Valid,
+    Missing,
+    Invalid,
+}
+
+impl Display for SignatureStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Valid => "valid",
+            Self::Missing => "missing",
+            Self::Invalid => "invalid",
+        })
+    }
+}
+
+/// The status of every signature `tx` needs, in the order of its signers.
+pub fn signature_statuses(tx: &Transaction) -> Vec<(Pubkey, SignatureStatus)> {
+    let message = tx.message_data();
+    tx.message
+        .signer_keys()
+        .into_iter()
+        .zip(&tx.signatures)
+        .map(|(key, signature)| {
+            let status = if *signature == Signature::default() {
+                SignatureStatus::Missing
+            } else if signature.verify(key.as_ref(), &message) {
+                SignatureStatus::Valid
+            } else {
+                SignatureStatus::Invalid
+            };
+            (*key, status)
+        })
+        .collect()
+}
+
+/// Check the signature of `signer` in `tx`, whatever the other signers did.
+pub fn verify_transaction(tx: &Transaction, signer: &Pubkey) -> Result<(), Error> {
+    match signature_statuses(tx).into_iter().find(|(key, _)| key == signer) {
+        None => Err(Error::NotASigner(*signer)),
+        Some((_, SignatureStatus::Missing)) => Err(Error::MissingSigner(*signer)),
+        Some((_, SignatureStatus::Invalid)) => Err(Error::InvalidSignature),
+        Some((_, SignatureStatus::Valid)) => Ok(()),
+    }
+}
+
+/// Check a signature of `signer` on arbitrary bytes.
+pub fn verify_message(signer: &Pubkey, message: &[u8], signature: &Signature) -> Result<(), Error> {
+    if !signature.verify(signer.as_ref(), message) {
+        return Err(Error::InvalidSignature);
+    }
+    Ok(())
+}
+
+/// Decode the bytes of a message, e.g. a serialized transaction message.
+pub fn decode_bytes(s: &str, encoding: Encoding) -> Result<Vec<u8>, Error> {
+    let s = s.trim();
+    match encoding {
+        Encoding::Base58 => Ok(bs58::decode(s).into_vec()?),
+        Encoding::Base64 => base64::decode(s).map_err(|e| Error::InvalidMessage(e.to_string())),
+        Encoding::Hex => hex::decode(s).map_err(|e| Error::InvalidMessage(e.to_string())),
+        Encoding::Json => Err(Error::UnsupportedEncoding(encoding)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::hash::Hash;
+    use solana_sdk::message::Message;
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::signature::{Keypair, Signer};
+    use solana_sdk::system_instruction;
+    use solana_sdk::transaction::Transaction;
+
+    use crate::serialization::Encoding;
+    use crate::verify::{decode_bytes, signature_statuses, verify_message, verify_transaction, SignatureStatus};
+    use crate::Error;
+
+    #[test]
+    fn test_verify_transaction() {
+        let (from, fee_payer) = (Keypair::new(), Keypair::new());
+        let instruction = system_instruction::transfer(&from.pubkey(), &Pubkey::new_unique(), 1000);
+        let mut tx = Transaction::new_unsigned(Message::new(&[instruction], Some(&fee_payer.pubkey())));
+        tx.message.recent_blockhash = Hash::new_unique();
+        tx.partial_sign(&[&from], tx.message.recent_blockhash);
+
+        assert_eq!(
+            signature_statuses(&tx),
+            vec![(fee_payer.pubkey(), SignatureStatus::Missing), (from.pubkey(), SignatureStatus::Valid)]
+        );
+        verify_transaction(&tx, &from.pubkey()).unwrap();
+        assert!(matches!(verify_transaction(&tx, &fee_payer.pubkey()), Err(Error::MissingSigner(_))));
+        assert!(matches!(verify_transaction(&tx, &Pubkey::new_unique()), Err(Error::NotASigner(_))));
+
+        // A signature of another message is in the right slot, but doesn't verify.
+        tx.signatures[0] = fee_payer.sign_message(b"something else");
+        assert_eq!(signature_statuses(&tx)[0], (fee_payer.pubkey(), SignatureStatus::Invalid));
+        assert!(matches!(verify_transaction(&tx, &fee_payer.pubkey()), Err(Error::InvalidSignature)));
+    }
+
+    #[test]
+    fn test_verify_message() {
+        let keypair = Keypair::new();
+        let message = b"solana-tss";
+        let signature = keypair.sign_message(message);
+        for (encoded, encoding) in [
+            (bs58::encode(message).into_string(), Encoding::Base58),
+            (base64::encode(message), Encoding::Base64),
+            (hex::encode(message), Encoding::Hex),
+        ] {
+            let decoded = decode_bytes(&encoded, encoding).unwrap();
+            verify_message(&keypair.pubkey(), &decoded, &signature).unwrap();
+        }
+        assert!(matches!(verify_message(&Pubkey::new_unique(), message, &signature), Err(Error::InvalidSignature)));
+        assert!(matches!(decode_bytes("zz", Encoding::Hex), Err(Error::InvalidMessage(_))));
+        assert!(matches!(decode_bytes("{}", Encoding::Json), Err(Error::UnsupportedEncoding(Encoding::Json))));
+    }
+}
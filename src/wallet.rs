@@ -0,0 +1,248 @@
+//! The setup ceremony, in which every party proves it holds the share behind its key before the aggregate address
+//! is used.
+//!
+//! A proof is a signature of a challenge binding the whole key set and its aggregate address, so it can't be reused
+//! for another wallet, and nobody can prove a key they computed from the others' keys without knowing its secret.
+//! Verified wallets are kept as descriptors in `SOLANA_TSS_HOME/wallets`, and the signing commands refuse key sets
+//! without one.
+
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+
+use crate::tss;
+use crate::Error;
+
+const CHALLENGE_DOMAIN: &[u8] = b"solana-tss/proof-of-possession/v1";
+const DESCRIPTOR_VERSION: u64 = 1;
+
+/// A party's signature of the challenge of a key set.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Proof {
+    pub key: Pubkey,
+    pub signature: Signature,
+}
+
+impl FromStr for Proof {
+    type Err = Error;
+    /// Parse `<ADDRESS>=<SIGNATURE>`, as printed by `setup prove`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidProof(format!("{} isn't <ADDRESS>=<SIGNATURE>", s));
+        let (key, signature) = s.split_once('=').ok_or_else(invalid)?;
+        Ok(Proof {
+            key: key.trim().parse().map_err(|_| invalid())?,
+            signature: signature.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl Display for Proof {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.key, self.signature)
+    }
+}
+
+/// A key set whose every party proved it holds its share.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Descriptor {
+    /// In their canonical order.
+    pub keys: Vec<Pubkey>,
+    pub aggregate: Pubkey,
+    /// In the order of the keys.
+    pub proofs: Vec<Proof>,
+}
+
+impl Descriptor {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "version": DESCRIPTOR_VERSION,
+            "aggregate": self.aggregate.to_string(),
+            "keys": self.keys.iter().map(Pubkey::to_string).collect::<Vec<_>>(),
+            "proofs": self.proofs.iter().map(Proof::to_string).collect::<Vec<_>>(),
+        })
+    }
+
+    /// Parse a descriptor and check every proof again, so editing the file can't sneak in another key.
+    pub fn from_json(value: &Value) -> Result<Self, Error> {
+        let invalid = |field: &str| Error::InvalidDescriptor(format!("missing or invalid {}", field));
+        if value["version"].as_u64() != Some(DESCRIPTOR_VERSION) {
+            return Err(invalid("version"));
+        }
+        let keys: Vec<Pubkey> = value["keys"]
+            .as_array()
+            .ok_or_else(|| invalid("keys"))?
+            .iter()
+            .map(|key| key.as_str().and_then(|key| key.parse().ok()).ok_or_else(|| invalid("keys")))
+            .collect::<Result<_, _>>()?;
+        let proofs: Vec<Proof> = value["proofs"]
+            .as_array()
+            .ok_or_else(|| invalid("proofs"))?
+            .iter()
+            .map(|proof| proof.as_str().ok_or_else(|| invalid("proofs"))?.parse())
+            .collect::<Result<_, _>>()?;
+        let descriptor = verify(&keys, &proofs)?;
+        if value["aggregate"].as_str() != Some(descriptor.aggregate.to_string().as_str()) {
+            return Err(invalid("aggregate"));
+        }
+        Ok(descriptor)
+    }
+}
+
+/// What every party signs: the domain, the keys in their canonical order, and the aggregate address.
+pub fn challenge(keys: &[Pubkey]) -> Result<Vec<u8>, Error> {
+    let aggkey = tss::aggregate_keys(keys.to_vec(), None)?;
+    let mut challenge = CHALLENGE_DOMAIN.to_vec();
+    for key in &aggkey.keys {
+        challenge.extend_from_slice(key.as_ref());
+    }
+    challenge.extend_from_slice(aggkey.aggregate.as_ref());
+    Ok(challenge)
+}
+
+/// Prove that `keypair` holds its share of the key set.
+pub fn prove(keypair: &Keypair, keys: &[Pubkey]) -> Result<Proof, Error> {
+    if !keys.contains(&keypair.pubkey()) {
+        return Err(Error::KeyPairIsNotInKeys);
+    }
+    Ok(Proof { key: keypair.pubkey(), signature: keypair.sign_message(&challenge(keys)?) })
+}
+
+/// Check that there's exactly one valid proof for every key, and nothing else.
+pub fn verify(keys: &[Pubkey], proofs: &[Proof]) -> Result<Descriptor, Error> {
+    let aggkey = tss::aggregate_keys(keys.to_vec(), None)?;
+    let challenge = challenge(keys)?;
+    if let Some(stranger) = proofs.iter().find(|proof| !aggkey.keys.contains(&proof.key)) {
+        return Err(Error::InvalidProof(format!("{} isn't one of the keys", stranger.key)));
+    }
+    let mut ordered = Vec::with_capacity(aggkey.keys.len());
+    for key in &aggkey.keys {
+        let mut key_proofs = proofs.iter().filter(|proof| proof.key == *key);
+        let proof = match (key_proofs.next(), key_proofs.next()) {
+            (Some(proof), None) => proof,
+            (None, _) => return Err(Error::InvalidProof(format!("{} didn't prove it holds its share", key))),
+            (Some(_), Some(_)) => return Err(Error::InvalidProof(format!("{} has more than one proof", key))),
+        };
+        if !proof.signature.verify(key.as_ref(), &challenge) {
+            return Err(Error::InvalidProof(format!("the proof of {} doesn't sign this key set", key)));
+        }
+        ordered.push(*proof);
+    }
+    Ok(Descriptor { keys: aggkey.keys, aggregate: aggkey.aggregate, proofs: ordered })
+}
+
+/// The verified wallets, a descriptor per aggregate address.
+pub struct WalletStore {
+    dir: PathBuf,
+}
+
+impl WalletStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The wallets in the `home` directory, see [`crate::audit::home`].
+    pub fn in_home(home: &Path) -> Self {
+        Self::new(home.join("wallets"))
+    }
+
+    fn path(&self, aggregate: &Pubkey) -> PathBuf {
+        self.dir.join(format!("{}.json", aggregate))
+    }
+
+    /// Store a descriptor, returns where it was written.
+    pub fn save(&self, descriptor: &Descriptor) -> Result<PathBuf, Error> {
+        let path = self.path(&descriptor.aggregate);
+        let failed = |e| Error::FileFailed(path.clone(), e);
+        fs::create_dir_all(&self.dir).map_err(failed)?;
+        let json = serde_json::to_string_pretty(&descriptor.to_json()).expect("a JSON value can be serialized");
+        fs::write(&path, json).map_err(failed)?;
+        Ok(path)
+    }
+
+    /// The descriptor of the key set, with its proofs checked again, if it was verified on this machine.
+    pub fn load(&self, keys: &[Pubkey]) -> Result<Option<Descriptor>, Error> {
+        let aggregate = tss::aggregate_keys(keys.to_vec(), None)?.aggregate;
+        let path = self.path(&aggregate);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::FileFailed(path, e)),
+        };
+        let value = serde_json::from_str(&content).map_err(|e| Error::InvalidDescriptor(e.to_string()))?;
+        let descriptor = Descriptor::from_json(&value)?;
+        if descriptor.aggregate != aggregate {
+            return Err(Error::InvalidDescriptor(format!("{} describes another wallet", path.display())));
+        }
+        Ok(Some(descriptor))
+    }
+
+    /// Fail unless every party of the key set proved it holds its share, see `setup`.
+    pub fn require_verified(&self, keys: &[Pubkey]) -> Result<(), Error> {
+        match self.load(keys)? {
+            Some(_) => Ok(()),
+            None => Err(Error::UnverifiedWallet(tss::aggregate_keys(keys.to_vec(), None)?.aggregate)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::signature::{Keypair, Signer};
+
+    use crate::wallet::{prove, verify, Proof, WalletStore};
+    use crate::Error;
+
+    #[test]
+    fn test_proofs() {
+        let keys: Vec<_> = (0..3).map(|_| Keypair::new()).collect();
+        let pubkeys: Vec<_> = keys.iter().map(|k| k.pubkey()).collect();
+        let mut proofs: Vec<_> = keys.iter().map(|k| prove(k, &pubkeys).unwrap()).collect();
+        let proof: Proof = proofs[0].to_string().parse().unwrap();
+        assert_eq!(proof, proofs[0]);
+
+        // The order of the keys and the proofs doesn't matter.
+        proofs.reverse();
+        let descriptor = verify(&pubkeys, &proofs).unwrap();
+        assert_eq!(descriptor.proofs[0].key, descriptor.keys[0]);
+
+        // A proof for another key set doesn't count, even with the same key in it.
+        let other_set = [pubkeys[0], Pubkey::new_unique()];
+        let mut replayed = proofs.clone();
+        replayed[2] = prove(&keys[0], &other_set).unwrap();
+        assert!(matches!(verify(&pubkeys, &replayed), Err(Error::InvalidProof(_))));
+        assert!(matches!(verify(&pubkeys, &proofs[1..]), Err(Error::InvalidProof(_))));
+        let mut duplicated = proofs.clone();
+        duplicated.push(proofs[0]);
+        assert!(matches!(verify(&pubkeys, &duplicated), Err(Error::InvalidProof(_))));
+        assert!(matches!(prove(&Keypair::new(), &pubkeys), Err(Error::KeyPairIsNotInKeys)));
+    }
+
+    #[test]
+    fn test_wallet_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = WalletStore::new(dir.path().join("wallets"));
+        let keys: Vec<_> = (0..2).map(|_| Keypair::new()).collect();
+        let pubkeys: Vec<_> = keys.iter().map(|k| k.pubkey()).collect();
+        assert!(matches!(store.require_verified(&pubkeys), Err(Error::UnverifiedWallet(_))));
+
+        let proofs: Vec<_> = keys.iter().map(|k| prove(k, &pubkeys).unwrap()).collect();
+        let descriptor = verify(&pubkeys, &proofs).unwrap();
+        let path = store.save(&descriptor).unwrap();
+        store.require_verified(&[pubkeys[1], pubkeys[0]]).unwrap();
+        assert_eq!(store.load(&pubkeys).unwrap(), Some(descriptor));
+
+        // Swapping in a key without a proof of it breaks the descriptor.
+        let content = fs::read_to_string(&path).unwrap();
+        fs::write(&path, content.replace(&pubkeys[1].to_string(), &Pubkey::new_unique().to_string())).unwrap();
+        assert!(store.require_verified(&pubkeys).is_err());
+    }
+}
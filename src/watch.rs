@@ -0,0 +1,266 @@
+//! Follow an address in real time over the pubsub websocket API.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use serde_json::{json, Value};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::pubsub_client::{
+    PubsubAccountClientSubscription, PubsubClient, PubsubLogsClientSubscription, PubsubProgramClientSubscription,
+};
+use solana_client::rpc_config::{
+    RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcTransactionLogsConfig, RpcTransactionLogsFilter,
+};
+use solana_client::rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::cli::format_sol;
+use crate::review::TOKEN_PROGRAM_ID;
+use crate::Error;
+
+/// The size of an SPL token account, its mint is at offset 0, its owner at 32 and its amount at 64.
+const TOKEN_ACCOUNT_LEN: usize = 165;
+const TOKEN_OWNER_OFFSET: usize = 32;
+const TOKEN_AMOUNT_OFFSET: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WatchEvent {
+    /// The SOL balance of the address changed, a positive change is an incoming transfer.
+    Balance { slot: u64, lamports: u64, change: i128 },
+    /// A transaction mentioning the address was processed.
+    Transaction { slot: u64, signature: String, err: Option<String> },
+    /// A token account owned by the address changed, an account created while watching counts from zero.
+    TokenAccount { slot: u64, account: Pubkey, mint: Pubkey, amount: u64, change: i128 },
+}
+
+impl WatchEvent {
+    pub fn to_json(&self) -> Value {
+        match self {
+            Self::Balance { slot, lamports, change } => {
+                json!({ "type": "balance", "slot": slot, "lamports": lamports, "change": change.to_string() })
+            }
+            Self::Transaction { slot, signature, err } => {
+                json!({ "type": "transaction", "slot": slot, "signature": signature, "err": err })
+            }
+            Self::TokenAccount { slot, account, mint, amount, change } => json!({
+                "type": "token_account",
+                "slot": slot,
+                "account": account.to_string(),
+                "mint": mint.to_string(),
+                "amount": amount,
+                "change": change.to_string(),
+            }),
+        }
+    }
+}
+
+impl Display for WatchEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Balance { slot, lamports, change } => {
+                let direction = if *change < 0 { "Sent" } else { "Received" };
+                let change = format_sol(change.unsigned_abs().try_into().unwrap_or(u64::MAX));
+                write!(
+                    f,
+                    "[slot {}] {} {} SOL, the balance is now {} SOL",
+                    slot,
+                    direction,
+                    change,
+                    format_sol(*lamports)
+                )
+            }
+            Self::Transaction { slot, signature, err: None } => write!(f, "[slot {}] Transaction {}", slot, signature),
+            Self::Transaction { slot, signature, err: Some(err) } => {
+                write!(f, "[slot {}] Transaction {} failed: {}", slot, signature, err)
+            }
+            Self::TokenAccount { slot, account, mint, amount, change } => write!(
+                f,
+                "[slot {}] Token account {} of mint {} changed by {:+}, the amount is now {}",
+                slot, account, mint, change, amount
+            ),
+        }
+    }
+}
+
+/// Live subscriptions for one address, they're closed when this is dropped.
+pub struct Watcher {
+    events: Receiver<WatchEvent>,
+    _account: PubsubAccountClientSubscription,
+    _logs: PubsubLogsClientSubscription,
+    _tokens: PubsubProgramClientSubscription,
+}
+
+impl Watcher {
+    /// The events as they arrive, this ends once every subscription was closed.
+    pub fn events(&self) -> impl Iterator<Item = WatchEvent> + '_ {
+        self.events.iter()
+    }
+}
+
+/// Subscribe to the balance, the transactions and the token accounts of `address`.
+/// `balance` and `token_amounts` are read before subscribing, so the first change of each can be reported.
+pub fn watch(
+    ws_url: &str,
+    address: &Pubkey,
+    commitment: CommitmentConfig,
+    balance: u64,
+    token_amounts: HashMap<Pubkey, u64>,
+) -> Result<Watcher, Error> {
+    let (events_sender, events) = mpsc::channel();
+
+    let (account, account_updates) = PubsubClient::account_subscribe(ws_url, address, Some(account_config(commitment)))
+        .map_err(Error::SubscriptionFailed)?;
+    let sender = events_sender.clone();
+    thread::spawn(move || {
+        let mut last = balance;
+        for update in account_updates {
+            let lamports = update.value.lamports;
+            let change = i128::from(lamports) - i128::from(last);
+            last = lamports;
+            let event = WatchEvent::Balance { slot: update.context.slot, lamports, change };
+            if change != 0 && sender.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    let filter = RpcTransactionLogsFilter::Mentions(vec![address.to_string()]);
+    let (logs, log_updates) =
+        PubsubClient::logs_subscribe(ws_url, filter, RpcTransactionLogsConfig { commitment: Some(commitment) })
+            .map_err(Error::SubscriptionFailed)?;
+    let sender = events_sender.clone();
+    thread::spawn(move || {
+        for update in log_updates {
+            let event = WatchEvent::Transaction {
+                slot: update.context.slot,
+                signature: update.value.signature,
+                err: update.value.err.map(|err| err.to_string()),
+            };
+            if sender.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    let token_config = token_accounts_config(address, commitment);
+    let (tokens, token_updates) = PubsubClient::program_subscribe(ws_url, &token_program(), Some(token_config))
+        .map_err(Error::SubscriptionFailed)?;
+    thread::spawn(move || {
+        let mut amounts = token_amounts;
+        for update in token_updates {
+            let account = match Pubkey::from_str(&update.value.pubkey) {
+                Ok(account) => account,
+                Err(_) => continue,
+            };
+            let (mint, amount) = match update.value.account.data.decode().as_deref().and_then(token_account) {
+                Some(token) => token,
+                None => continue,
+            };
+            let previous = amounts.insert(account, amount).unwrap_or(0);
+            let change = i128::from(amount) - i128::from(previous);
+            let event = WatchEvent::TokenAccount { slot: update.context.slot, account, mint, amount, change };
+            if events_sender.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(Watcher { events, _account: account, _logs: logs, _tokens: tokens })
+}
+
+fn account_config(commitment: CommitmentConfig) -> RpcAccountInfoConfig {
+    RpcAccountInfoConfig {
+        encoding: Some(UiAccountEncoding::Base64),
+        commitment: Some(commitment),
+        ..RpcAccountInfoConfig::default()
+    }
+}
+
+pub fn token_program() -> Pubkey {
+    Pubkey::from_str(TOKEN_PROGRAM_ID).expect("the token program ID is valid")
+}
+
+/// Selects the token accounts owned by `owner`, among the accounts of the token program.
+pub fn token_accounts_config(owner: &Pubkey, commitment: CommitmentConfig) -> RpcProgramAccountsConfig {
+    RpcProgramAccountsConfig {
+        filters: Some(vec![
+            RpcFilterType::DataSize(TOKEN_ACCOUNT_LEN as u64),
+            RpcFilterType::Memcmp(Memcmp {
+                offset: TOKEN_OWNER_OFFSET,
+                bytes: MemcmpEncodedBytes::Base58(owner.to_string()),
+                encoding: None,
+            }),
+        ]),
+        account_config: account_config(commitment),
+        ..RpcProgramAccountsConfig::default()
+    }
+}
+
+/// The mint and the amount of a token account.
+pub fn token_account(data: &[u8]) -> Option<(Pubkey, u64)> {
+    if data.len() != TOKEN_ACCOUNT_LEN {
+        return None;
+    }
+    let mint = Pubkey::new(&data[..32]);
+    let amount = u64::from_le_bytes(data[TOKEN_AMOUNT_OFFSET..TOKEN_AMOUNT_OFFSET + 8].try_into().ok()?);
+    Some((mint, amount))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use solana_sdk::commitment_config::CommitmentConfig;
+    use solana_sdk::native_token::LAMPORTS_PER_SOL;
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::signature::{Keypair, Signer};
+    use solana_streamer::socket::SocketAddrSpace;
+    use solana_test_validator::TestValidator;
+
+    use crate::chain::Chain;
+    use crate::create_unsigned_transaction;
+    use crate::watch::{token_account, watch, WatchEvent};
+
+    #[test]
+    fn test_token_account() {
+        let (mint, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut data = vec![0u8; 165];
+        data[..32].copy_from_slice(mint.as_ref());
+        data[32..64].copy_from_slice(owner.as_ref());
+        data[64..72].copy_from_slice(&42u64.to_le_bytes());
+        assert_eq!(token_account(&data), Some((mint, 42)));
+        assert_eq!(token_account(&data[..100]), None);
+    }
+
+    #[test]
+    fn test_watch() {
+        let payer = Keypair::new();
+        let testnet = TestValidator::with_no_fees(payer.pubkey(), None, SocketAddrSpace::Unspecified);
+        let rpc_client = testnet.get_rpc_client();
+        let watched = Pubkey::new_unique();
+
+        let token_amounts = rpc_client.token_amounts(&watched).unwrap();
+        assert!(token_amounts.is_empty());
+        let watcher =
+            watch(&testnet.rpc_pubsub_url(), &watched, CommitmentConfig::confirmed(), 0, token_amounts).unwrap();
+        let mut tx = create_unsigned_transaction(LAMPORTS_PER_SOL, &watched, None, &payer.pubkey());
+        tx.sign(&[&payer], rpc_client.get_latest_blockhash().unwrap());
+        rpc_client.send_and_confirm_transaction(&tx).unwrap();
+
+        let (mut balance, mut transaction) = (None, None);
+        while balance.is_none() || transaction.is_none() {
+            match watcher.events.recv_timeout(Duration::from_secs(30)).unwrap() {
+                WatchEvent::Balance { lamports, change, .. } => balance = Some((lamports, change)),
+                WatchEvent::Transaction { signature, err, .. } => transaction = Some((signature, err)),
+                WatchEvent::TokenAccount { .. } => panic!("no token account was touched"),
+            }
+        }
+        assert_eq!(balance, Some((LAMPORTS_PER_SOL, i128::from(LAMPORTS_PER_SOL))));
+        assert_eq!(transaction, Some((tx.signatures[0].to_string(), None)));
+    }
+}
@@ -0,0 +1,95 @@
+//! The wire format of fully signed transactions, so they can be handed over to a relayer and broadcast later.
+
+use std::fs;
+use std::path::Path;
+
+use solana_sdk::packet::PACKET_DATA_SIZE;
+use solana_sdk::sanitize::Sanitize;
+use solana_sdk::transaction::Transaction;
+
+use crate::serialization::Encoding;
+use crate::Error;
+
+pub fn serialize(tx: &Transaction) -> Vec<u8> {
+    bincode::serialize(tx).expect("serializing a transaction into a vector can't fail")
+}
+
+pub fn deserialize(bytes: &[u8]) -> Result<Transaction, Error> {
+    if bytes.len() > PACKET_DATA_SIZE {
+        return Err(Error::InvalidTransaction(format!("{} bytes is larger than a packet", bytes.len())));
+    }
+    let tx: Transaction = bincode::deserialize(bytes).map_err(|e| Error::InvalidTransaction(e.to_string()))?;
+    if serialize(&tx).len() != bytes.len() {
+        return Err(Error::InvalidTransaction("trailing bytes after the transaction".to_string()));
+    }
+    tx.sanitize().map_err(|e| Error::InvalidTransaction(e.to_string()))?;
+    Ok(tx)
+}
+
+pub fn encode(tx: &Transaction, encoding: Encoding) -> Result<String, Error> {
+    let bytes = serialize(tx);
+    match encoding {
+        Encoding::Base58 => Ok(bs58::encode(bytes).into_string()),
+        Encoding::Base64 => Ok(base64::encode(bytes)),
+        Encoding::Hex => Ok(hex::encode(bytes)),
+        Encoding::Json => Err(Error::UnsupportedEncoding(encoding)),
+    }
+}
+
+/// Decode a transaction encoded as base58, base64 or hex, without knowing which one was used.
+pub fn decode(s: &str) -> Result<Transaction, Error> {
+    let s = s.trim();
+    let candidates = [bs58::decode(s).into_vec().ok(), base64::decode(s).ok(), hex::decode(s).ok()];
+    let mut first_error = None;
+    for bytes in candidates.iter().flatten() {
+        match deserialize(bytes) {
+            Ok(tx) => return Ok(tx),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    Err(first_error.unwrap_or_else(|| Error::InvalidTransaction("not base58, base64 or hex".to_string())))
+}
+
+/// Files hold the raw wire format.
+pub fn write_file(path: &Path, tx: &Transaction) -> Result<(), Error> {
+    fs::write(path, serialize(tx)).map_err(|e| Error::FileFailed(path.to_path_buf(), e))
+}
+
+pub fn read_file(path: &Path) -> Result<Transaction, Error> {
+    deserialize(&fs::read(path).map_err(|e| Error::FileFailed(path.to_path_buf(), e))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::hash::Hash;
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::signature::{Keypair, Signer};
+
+    use crate::create_unsigned_transaction;
+    use crate::serialization::Encoding;
+    use crate::wire::{decode, encode, read_file, write_file};
+    use crate::Error;
+
+    #[test]
+    fn test_wire_roundtrip() {
+        let keypair = Keypair::new();
+        let mut tx =
+            create_unsigned_transaction(42, &Pubkey::new_unique(), Some("wire".to_string()), &keypair.pubkey());
+        tx.sign(&[&keypair], Hash::new_unique());
+
+        for encoding in [Encoding::Base58, Encoding::Base64, Encoding::Hex] {
+            let encoded = encode(&tx, encoding).unwrap();
+            assert_eq!(decode(&encoded).unwrap(), tx);
+        }
+        assert!(matches!(encode(&tx, Encoding::Json), Err(Error::UnsupportedEncoding(Encoding::Json))));
+        assert!(matches!(decode("not a transaction!"), Err(Error::InvalidTransaction(_))));
+        assert!(matches!(decode(&bs58::encode([1, 2, 3]).into_string()), Err(Error::InvalidTransaction(_))));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transfer.tx");
+        write_file(&path, &tx).unwrap();
+        assert_eq!(read_file(&path).unwrap(), tx);
+    }
+}